@@ -0,0 +1,251 @@
+use std::{ env, fs, io::{ self, BufRead, BufReader, Read, Write }, os::{ fd::AsRawFd, unix::{ fs::{ DirBuilderExt, MetadataExt, PermissionsExt }, net::{ UnixListener, UnixStream } } }, path::{ Path, PathBuf }, thread, time };
+use super::secret::SecretString;
+
+pub const DEFAULT_TTL: u64 = 3600;
+const POLL_TIME: time::Duration = time::Duration::from_millis(100);
+const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+/// Longest request line we bother reading, names don't get anywhere near this
+const MAX_REQUEST: u64 = 4096;
+
+// Protocol, one request per connection:
+//     client sends the canonical path of its data file and a request, a line each
+//     agent answers `ok` and the output, or `err` and a message, then hangs up
+pub enum Request {
+    /// Names of every password and TOTP code
+    List,
+    /// One password, or all of them like `--pass`
+    Pass(Option<String>),
+    /// One current TOTP code, or all of them like `--totp`
+    Totp(Option<String>),
+    /// Everything about a password except the secret bits
+    Show(String),
+    /// One field of a password, name and field separated by a tab on the wire
+    Field(String, String),
+    /// Old passwords of a password, newest first
+    History(String),
+    /// Passwords that expire or are due to be changed within this many days
+    Due(i64),
+    /// Weak, reused and old passwords, as JSON or not, checked against a breached password list if there's one
+    Audit(bool, Option<String>),
+    /// A TOTP code as an `otpauth://` URI, secret and all
+    Uri(String),
+    /// Every TOTP code as JSON, the same as in the data file, for writing authenticator backups
+    Codes,
+    Stop,
+}
+
+impl Request {
+    fn parse(line: &str) -> Option<Request> {
+        let (command, name) = match line.split_once(' ') {
+            Some((command, name)) => (command, Some(name.to_string())),
+            None => (line, None),
+        };
+
+        match (command, name) {
+            ("list", None) => Some(Request::List),
+            ("pass", name) => Some(Request::Pass(name)),
+            ("totp", name) => Some(Request::Totp(name)),
+            ("show", Some(name)) => Some(Request::Show(name)),
+            ("field", Some(name_key)) => name_key.split_once('\t').map(|(name, key)| Request::Field(name.to_string(), key.to_string())),
+            ("history", Some(name)) => Some(Request::History(name)),
+            ("due", Some(days)) => days.parse().ok().map(Request::Due),
+            ("audit", Some(options)) => {
+                let (format, breaches) = match options.split_once('\t') {
+                    Some((format, breaches)) => (format.to_string(), Some(breaches.to_string())),
+                    None => (options, None),
+                };
+                match format.as_str() {
+                    "text" => Some(Request::Audit(false, breaches)),
+                    "json" => Some(Request::Audit(true, breaches)),
+                    _ => None,
+                }
+            },
+            ("uri", Some(name)) => Some(Request::Uri(name)),
+            ("codes", None) => Some(Request::Codes),
+            ("stop", None) => Some(Request::Stop),
+            _ => None,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        match self {
+            Request::List => "list".to_string(),
+            Request::Pass(None) => "pass".to_string(),
+            Request::Pass(Some(name)) => format!("pass {}", name),
+            Request::Totp(None) => "totp".to_string(),
+            Request::Totp(Some(name)) => format!("totp {}", name),
+            Request::Show(name) => format!("show {}", name),
+            Request::Field(name, key) => format!("field {}\t{}", name, key),
+            Request::History(name) => format!("history {}", name),
+            Request::Due(days) => format!("due {}", days),
+            Request::Audit(json, breaches) => {
+                let format = if *json { "json" } else { "text" };
+                match breaches {
+                    Some(breaches) => format!("audit {}\t{}", format, breaches),
+                    None => format!("audit {}", format),
+                }
+            },
+            Request::Uri(name) => format!("uri {}", name),
+            Request::Codes => "codes".to_string(),
+            Request::Stop => "stop".to_string(),
+        }
+    }
+}
+
+/// `PASSRS_AGENT_SOCK`, otherwise somewhere only we can get at
+pub fn socket_path() -> PathBuf {
+    if let Ok(path) = env::var("PASSRS_AGENT_SOCK") {
+        return PathBuf::from(path);
+    }
+
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => Path::new(&dir).join("passrs-agent"),
+        _ => env::temp_dir().join(format!("passrs-{}", unsafe { libc::geteuid() })).join("agent"),
+    }
+}
+
+/// Binds the socket, readable and writable only by us, clearing out one left behind by a dead agent
+pub fn listen(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        if !dir.exists() {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+
+        // Anyone who can write to the directory could swap the socket out from under us
+        let meta = fs::metadata(dir)?;
+        if meta.uid() != unsafe { libc::geteuid() } || (meta.mode() & 0o022 != 0 && meta.mode() & 0o1000 == 0) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("`{}` is writable by other users", dir.display())));
+        }
+    }
+
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "an agent is already running"));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answers requests for `file` until the TTL runs out or a client asks it to stop
+pub fn serve(listener: UnixListener, file: &Path, ttl: time::Duration, mut answer: impl FnMut(&Request) -> Result<SecretString, String>) -> io::Result<()> {
+    let deadline = time::Instant::now() + ttl;
+    listener.set_nonblocking(true)?;
+
+    while time::Instant::now() < deadline {
+        match listener.accept() {
+            // A client that hangs up or misbehaves only loses its own answer
+            Ok((stream, _)) => {
+                if let Ok(Some(Request::Stop)) = handle_client(stream, file, &mut answer) {
+                    break;
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_TIME),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_client(stream: UnixStream, file: &Path, answer: &mut impl FnMut(&Request) -> Result<SecretString, String>) -> io::Result<Option<Request>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+
+    // The socket's mode should already keep others out, but some systems ignore it
+    if peer_uid(&stream)? != unsafe { libc::geteuid() } {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST));
+    let mut client_file = String::new();
+    let mut line = String::new();
+    reader.read_line(&mut client_file)?;
+    reader.read_line(&mut line)?;
+
+    if Path::new(client_file.trim_end_matches('\n')) != file {
+        writer.write_all(format!("err it has `{}` open, not this data file\n", file.display()).as_bytes())?;
+        return Ok(None);
+    }
+
+    let request = match Request::parse(line.trim_end_matches('\n')) {
+        Some(request) => request,
+        None => {
+            writer.write_all(b"err unknown request\n")?;
+            return Ok(None);
+        },
+    };
+
+    match answer(&request) {
+        Ok(output) => {
+            writer.write_all(b"ok\n")?;
+            writer.write_all(output.as_bytes())?;
+        },
+        Err(message) => writer.write_all(format!("err {}\n", message).as_bytes())?,
+    }
+    Ok(Some(request))
+}
+
+/// Asks a running agent, `Err` if there isn't one, `Ok(Err)` if it answered with an error
+pub fn query(socket: &Path, file: &Path, request: &Request) -> io::Result<Result<SecretString, String>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n{}\n", file.display(), request.to_line()).as_bytes())?;
+
+    let mut response = SecretString::with_capacity(4096);
+    stream.read_to_string(&mut response)?;
+
+    if response.starts_with("ok\n") {
+        response.drain(..3);
+        Ok(Ok(response))
+    } else if let Some(message) = response.strip_prefix("err ") {
+        Ok(Err(message.trim_end().to_string()))
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "agent gave a malformed answer"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+
+    if result == 0 { Ok(cred.uid) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == 0 {
+        Ok(uid)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Forks into the background, detached from the terminal. Only returns in the child.
+pub fn daemonize() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {},
+        pid => {
+            println!("passrs agent running with pid {}", pid);
+            std::process::exit(0);
+        },
+    }
+
+    unsafe {
+        libc::setsid();
+        let null = fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+        for fd in 0..3 {
+            libc::dup2(null.as_raw_fd(), fd);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::{ env, ffi::CStr, fs, io::{ self, BufRead, BufReader, Read, Write }, os::fd::FromRawFd, process::{ Command, Stdio } };
+use zeroize::Zeroize;
+use super::secret::SecretString;
+
+/// gpg-error code pinentry answers with when the dialog is closed or cancelled
+const GPG_ERR_CANCELED: u32 = 99;
+
+/// Somewhere other than the environment to get the master password from
+pub enum PassSource {
+    /// Program speaking the Assuan pinentry protocol
+    Pinentry(String),
+    /// Shell command that prints the password
+    Command(String),
+    /// File descriptor inherited from whatever started passrs
+    Fd(i32),
+    File(String),
+}
+
+/// Gets the password, `Ok(None)` if the user cancelled the pinentry dialog.
+/// Only the first line counts, the trailing newline of `echo` and friends isn't part of the password.
+pub fn read(source: &PassSource) -> io::Result<Option<SecretString>> {
+    match source {
+        PassSource::Pinentry(program) => pinentry(program),
+        PassSource::Command(cmd) => {
+            let output = Command::new("sh").arg("-c").arg(cmd)
+                .stdin(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .output()?;
+            if !output.status.success() {
+                let mut stdout = output.stdout;
+                stdout.zeroize();
+                return Err(io::Error::other(format!("password command failed, {}", output.status)));
+            }
+            first_line(output.stdout).map(Some)
+        },
+        PassSource::Fd(fd) => {
+            // Owning a descriptor that isn't open aborts the whole process, rather than failing a read
+            if unsafe { libc::fcntl(*fd, libc::F_GETFD) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // Takes ownership of the descriptor, it's closed once read
+            let mut file = unsafe { fs::File::from_raw_fd(*fd) };
+            let mut bytes = Vec::with_capacity(1024);
+            file.read_to_end(&mut bytes)?;
+            first_line(bytes).map(Some)
+        },
+        PassSource::File(path) => first_line(fs::read(path)?).map(Some),
+    }
+}
+
+fn first_line(mut bytes: Vec<u8>) -> io::Result<SecretString> {
+    let end = bytes.iter().position(|&b| b == b'\n').unwrap_or(bytes.len());
+    bytes[end..].zeroize();
+    bytes.truncate(end);
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(string) => Ok(SecretString::new(string)),
+        Err(err) => {
+            err.into_bytes().zeroize();
+            Err(io::Error::new(io::ErrorKind::InvalidData, "password is not valid UTF-8"))
+        },
+    }
+}
+
+fn pinentry(program: &str) -> io::Result<Option<SecretString>> {
+    let mut child = Command::new(program).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+    let mut input = child.stdin.take().unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+
+    let pin = (|| {
+        let mut line = SecretString::with_capacity(1024);
+        if assuan_reply(&mut output, &mut line, None)?.is_err() {
+            return Err(io::Error::other("pinentry didn't start"));
+        }
+
+        let mut commands = vec![
+            "SETTITLE passrs".to_string(),
+            "SETDESC Enter the master password for the passrs data file".to_string(),
+            "SETPROMPT Password:".to_string(),
+        ];
+        // Curses pinentry needs to know which terminal to draw on
+        let tty = unsafe { libc::ttyname(0) };
+        if !tty.is_null() {
+            commands.push(format!("OPTION ttyname={}", unsafe { CStr::from_ptr(tty) }.to_string_lossy()));
+        }
+        if let Ok(term) = env::var("TERM") {
+            commands.push(format!("OPTION ttytype={}", term));
+        }
+
+        for command in commands {
+            writeln!(input, "{}", command)?;
+            // Not every pinentry knows every option, it'll still ask for the pin
+            let _ = assuan_reply(&mut output, &mut line, None)?;
+        }
+
+        writeln!(input, "GETPIN")?;
+        let mut pin = SecretString::with_capacity(1024);
+        match assuan_reply(&mut output, &mut line, Some(&mut pin))? {
+            Ok(()) => Ok(Some(pin)),
+            Err(code) if code & 0xffff == GPG_ERR_CANCELED => Ok(None),
+            Err(code) => Err(io::Error::other(format!("pinentry failed with error {}", code))),
+        }
+    })();
+
+    let _ = writeln!(input, "BYE");
+    drop(input);
+    let _ = child.wait();
+    pin
+}
+
+/// Reads lines up to the `OK` or `ERR code` that ends a reply, decoding any data lines into `data`
+fn assuan_reply(output: &mut impl BufRead, line: &mut SecretString, mut data: Option<&mut SecretString>) -> io::Result<Result<(), u32>> {
+    loop {
+        line.clear();
+        if output.read_line(line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pinentry exited unexpectedly"));
+        }
+        let reply = line.trim_end_matches(['\r', '\n']);
+
+        if reply == "OK" || reply.starts_with("OK ") {
+            return Ok(Ok(()));
+        } else if let Some(error) = reply.strip_prefix("ERR ") {
+            let code = error.split(' ').next().and_then(|c| c.parse().ok()).unwrap_or(0);
+            return Ok(Err(code));
+        } else if let (Some(encoded), Some(ref mut data)) = (reply.strip_prefix("D "), &mut data) {
+            percent_decode(encoded, data);
+        }
+        // Status (`S`) and comment (`#`) lines don't matter here
+    }
+}
+
+/// Assuan escapes `%`, CR and LF in data lines as `%XX`
+fn percent_decode(encoded: &str, decoded: &mut String) {
+    let mut chars = encoded.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let hex: String = chars.by_ref().take(2).collect();
+            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                decoded.push(byte as char);
+            }
+        } else {
+            decoded.push(c);
+        }
+    }
+}
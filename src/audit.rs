@@ -0,0 +1,223 @@
+use serde::Serialize;
+use std::io;
+use super::{ pass, totp, stamp, strength, breach };
+
+/// Passwords scoring below this are reported as weak
+const WEAK_SCORE: u8 = 3;
+/// Passwords unchanged for longer than this are reported as old
+const OLD_DAYS: i64 = 365;
+
+#[derive(Serialize)]
+pub struct Report {
+    pub weak: Vec<Weak>,
+    /// Names of passwords that are the same or nearly the same, one group each
+    pub reused: Vec<Vec<String>>,
+    pub old: Vec<Old>,
+    /// Passwords with no TOTP code that looks like it belongs to the same account
+    pub no_totp: Vec<String>,
+    /// Only checked when given a breached password list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breached: Option<Vec<Breached>>,
+}
+
+#[derive(Serialize)]
+pub struct Breached {
+    pub name: String,
+    /// Times it was seen in breaches
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+pub struct Weak {
+    pub name: String,
+    pub score: u8,
+    pub strength: &'static str,
+    pub guesses_log10: f64,
+}
+
+#[derive(Serialize)]
+pub struct Old {
+    pub name: String,
+    pub days: i64,
+}
+
+/// Lowercase letters and digits only, so `GitHub` matches `github.com`
+fn simplify(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + (a_char != b_char) as usize).min(row[j] + 1).min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Same password give or take a couple of characters or some capitals, like `hunter2` and `Hunter3`
+fn similar(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.to_lowercase().chars().collect(), b.to_lowercase().chars().collect());
+    a == b || (a.len().min(b.len()) >= 6 && edit_distance(&a, &b) <= 2)
+}
+
+pub fn audit(passwords: &[pass::Password], codes: &[totp::TotpCode]) -> Report {
+    let now = stamp::now();
+
+    let weak = passwords.iter().filter_map(|pass| {
+        let strength = strength::estimate(&pass.password);
+        (strength.score < WEAK_SCORE).then(|| Weak {
+            name: pass.name.clone(), score: strength.score, strength: strength.label(), guesses_log10: strength.guesses_log10,
+        })
+    }).collect();
+
+    // Each password joins the group of the first earlier one it's like
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (index, pass) in passwords.iter().enumerate().filter(|(_, pass)| !pass.password.is_empty()) {
+        match groups.iter_mut().find(|group| group.iter().any(|&other| similar(&passwords[other].password, &pass.password))) {
+            Some(group) => group.push(index),
+            None => groups.push(vec![index]),
+        }
+    }
+    let reused = groups.into_iter()
+        .filter(|group| group.len() > 1)
+        .map(|group| group.into_iter().map(|index| passwords[index].name.clone()).collect())
+        .collect();
+
+    // Passwords from before timestamps were kept don't have an age to go on
+    let old = passwords.iter().filter_map(|pass| {
+        let since = [pass.changed, pass.created].into_iter().find(|&time| time != 0)?;
+        let days = (now - since) / stamp::DAY;
+        (days > OLD_DAYS).then(|| Old { name: pass.name.clone(), days })
+    }).collect();
+
+    let code_names: Vec<String> = codes.iter().map(|code| simplify(&code.name)).filter(|name| !name.is_empty()).collect();
+    let no_totp = passwords.iter()
+        .filter(|pass| {
+            // A name with no letters or digits has nothing to match a code on, rather than matching every one
+            let name = simplify(&pass.name);
+            name.is_empty() || !code_names.iter().any(|code| name.contains(code.as_str()) || code.contains(name.as_str()))
+        })
+        .map(|pass| pass.name.clone())
+        .collect();
+
+    Report { weak, reused, old, no_totp, breached: None }
+}
+
+pub fn breached(passwords: &[pass::Password], dump: &mut breach::Dump) -> io::Result<Vec<Breached>> {
+    let mut breached = Vec::new();
+    for pass in passwords.iter().filter(|pass| !pass.password.is_empty()) {
+        if let Some(count) = dump.count(&pass.password)? {
+            breached.push(Breached { name: pass.name.clone(), count });
+        }
+    }
+    Ok(breached)
+}
+
+impl Report {
+    pub fn human(&self) -> String {
+        let mut text = String::new();
+        let mut section = |title: &str, lines: Vec<String>| {
+            text.push_str(&format!("{} ({})\n", title, lines.len()));
+            for line in lines {
+                text.push_str(&format!("    {}\n", line));
+            }
+        };
+
+        section("Weak passwords", self.weak.iter().map(|weak| format!("{}: {}", weak.name, weak.strength)).collect());
+        section("Reused passwords", self.reused.iter().map(|group| group.join(", ")).collect());
+        section(&format!("Unchanged for over {} days", OLD_DAYS), self.old.iter().map(|old| format!("{}: {} days", old.name, old.days)).collect());
+        section("Without a TOTP code", self.no_totp.clone());
+        if let Some(ref breached) = self.breached {
+            section("In known breaches", breached.iter().map(|breached| format!("{}: seen {} times", breached.name, breached.count)).collect());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ gen, secret::SecretString };
+
+    fn password(name: &str, password: &str) -> pass::Password {
+        let mut pass = pass::Password::new(&gen::fallback()).unwrap();
+        pass.name = name.to_string();
+        pass.password = SecretString::new(password.to_string());
+        pass
+    }
+
+    fn code(name: &str) -> totp::TotpCode {
+        let mut code = totp::TotpCode::new();
+        code.name = name.to_string();
+        code
+    }
+
+    #[test]
+    fn reused() {
+        let passwords = [
+            password("a", "correct-horse-7"),
+            password("b", "unrelated-thing"),
+            password("c", "correct-horse-7"),
+            // Two characters and some capitals off
+            password("d", "Correct-Horse-42"),
+            // Too short for near misses to count
+            password("e", "abc12"),
+            password("f", "abc13"),
+            password("g", ""),
+            password("h", ""),
+        ];
+        let report = audit(&passwords, &[]);
+        assert_eq!(report.reused, [["a", "c", "d"]]);
+
+        assert!(similar("hunter22", "Hunter23"));
+        assert!(similar("abc", "ABC"));
+        assert!(!similar("hunter22", "hunt3r2345"));
+        assert_eq!(edit_distance(&['k', 'i', 't', 't', 'e', 'n'], &['s', 'i', 't', 't', 'i', 'n', 'g']), 3);
+    }
+
+    #[test]
+    fn old() {
+        let now = stamp::now();
+        let mut passwords = [password("new", "x"), password("old", "x"), password("edited", "x"), password("untimed", "x")];
+        passwords[0].changed = now - (OLD_DAYS - 1) * stamp::DAY;
+        passwords[1].changed = now - (OLD_DAYS + 1) * stamp::DAY;
+        // Only changing the password itself counts
+        passwords[2].changed = now - (OLD_DAYS + 10) * stamp::DAY;
+        passwords[2].modified = now;
+        passwords[3].changed = 0;
+        passwords[3].created = 0;
+
+        let report = audit(&passwords, &[]);
+        let old: Vec<_> = report.old.iter().map(|old| (old.name.as_str(), old.days)).collect();
+        assert_eq!(old, [("old", OLD_DAYS + 1), ("edited", OLD_DAYS + 10)]);
+    }
+
+    #[test]
+    fn no_totp() {
+        let passwords = [
+            password("GitHub", "x"),
+            password("mail.example.com", "x"),
+            password("Bank", "x"),
+            password("!!!", "x"),
+        ];
+        let codes = [code("github.com:me"), code("Example Mail"), code("***")];
+        let report = audit(&passwords, &codes);
+        assert_eq!(report.no_totp, ["mail.example.com", "Bank", "!!!"]);
+
+        assert_eq!(audit(&passwords, &[]).no_totp.len(), passwords.len());
+    }
+
+    #[test]
+    fn weak() {
+        let passwords = [password("a", "password1"), password("b", "k8#Vq2!zP@w9")];
+        let report = audit(&passwords, &[]);
+        assert_eq!(report.weak.len(), 1);
+        assert_eq!((report.weak[0].name.as_str(), report.weak[0].score), ("a", 0));
+    }
+}
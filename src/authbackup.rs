@@ -0,0 +1,691 @@
+use std::{ fmt, fs, io::{ self, Write }, os::unix::fs::OpenOptionsExt, path::Path };
+use aes_gcm::{ aead::AeadInPlace, Aes256Gcm, KeyInit, Nonce, Tag };
+use base64ct::{ Base64, Encoding };
+use rand::{ thread_rng, RngCore };
+use serde::{ Serialize, Deserialize };
+use serde_json::Value;
+use zeroize::Zeroize;
+use super::{ otpauth::{ self, UriError }, totp::TotpCode, secret::SecretString };
+
+// Backups from other authenticator apps, to move codes between them and passrs:
+//     Aegis        `{"version": 1, "header": {...}, "db": {"entries": [...]}}`, where an encrypted vault has
+//                  the db as base64 AES-256-GCM under a master key, itself sealed in a slot per password with scrypt
+//     andOTP       `[{"secret": ..., "issuer": ..., "label": ..., "type": "TOTP", ...}]`, plain backups only
+//     2FAS         `{"services": [{"secret": ..., "otp": {...}}], "schemaVersion": 4, ...}`, unencrypted only
+// Secrets are base32 in all of them. Counter based HOTP and Steam codes get skipped, passrs only does TOTP
+
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// Past this a vault is more likely a way to eat all our memory than a real one
+const SCRYPT_MAX_LOG_N: u8 = 20;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Aegis,
+    AegisEncrypted,
+    AndOtp,
+    TwoFas,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Read(io::Error),
+    Write(io::Error),
+    Json(serde_json::Error),
+    UnknownFormat,
+    /// 2FAS and andOTP can encrypt their backups too, in ways we don't read
+    EncryptedUnsupported(&'static str),
+    PasswordRequired,
+    WrongPassword,
+    BadVault,
+    Entry(String, UriError),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::Read(err) => write!(f, "Cannot read authenticator backup: {}", err),
+            BackupError::Write(err) => write!(f, "Cannot write authenticator backup: {}", err),
+            BackupError::Json(err) => write!(f, "Authenticator backup isn't valid: {}", err),
+            BackupError::UnknownFormat => write!(f, "Authenticator backup isn't an Aegis, andOTP or 2FAS backup"),
+            BackupError::EncryptedUnsupported(app) => write!(f, "Encrypted {} backups aren't supported, export one without a password", app),
+            BackupError::PasswordRequired => write!(f, "Encrypted Aegis vaults need a password, give it in PASSRS_BACKUP_PASS"),
+            BackupError::WrongPassword => write!(f, "Cannot decrypt Aegis vault with the password in PASSRS_BACKUP_PASS"),
+            BackupError::BadVault => write!(f, "Aegis vault's encryption header is damaged"),
+            BackupError::Entry(name, err) => write!(f, "Cannot import `{}`, {}", name, err),
+        }
+    }
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "aegis" => Some(Format::Aegis),
+            "aegis-encrypted" => Some(Format::AegisEncrypted),
+            "andotp" => Some(Format::AndOtp),
+            "2fas" => Some(Format::TwoFas),
+            _ => None,
+        }
+    }
+}
+
+/// What's left once the HOTP and Steam codes are taken out
+pub struct Imported {
+    pub codes: Vec<TotpCode>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisFile {
+    version: u32,
+    header: AegisHeader,
+    /// An object in plain vaults, base64 in encrypted ones
+    db: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisHeader {
+    slots: Option<Vec<AegisSlot>>,
+    params: Option<AegisParams>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisParams {
+    nonce: String,
+    tag: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisSlot {
+    /// 1 for password slots, the only kind we can open
+    #[serde(rename = "type")]
+    kind: u32,
+    uuid: String,
+    key: String,
+    key_params: AegisParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    p: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repaired: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    is_backup: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisDb {
+    version: u32,
+    entries: Vec<AegisEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    uuid: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default)]
+    icon: Option<String>,
+    info: AegisInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AegisInfo {
+    secret: String,
+    #[serde(default = "default_algorithm")]
+    algo: String,
+    #[serde(default = "default_digits")]
+    digits: usize,
+    #[serde(default = "default_period")]
+    period: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AndOtpEntry {
+    secret: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default = "default_digits")]
+    digits: usize,
+    #[serde(rename = "type", default = "default_kind")]
+    kind: String,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    #[serde(default = "default_period")]
+    period: u64,
+    #[serde(default)]
+    thumbnail: String,
+    #[serde(default)]
+    last_used: i64,
+    #[serde(default)]
+    used_frequency: u64,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFasFile {
+    #[serde(default)]
+    services: Vec<TwoFasService>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    services_encrypted: Option<String>,
+    #[serde(default)]
+    groups: Vec<Value>,
+    #[serde(default)]
+    updated_at: i64,
+    #[serde(default)]
+    schema_version: u32,
+    #[serde(default)]
+    app_version_code: u32,
+    #[serde(default)]
+    app_version_name: String,
+    #[serde(default)]
+    app_origin: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFasService {
+    #[serde(default)]
+    name: String,
+    secret: String,
+    #[serde(default)]
+    updated_at: i64,
+    otp: TwoFasOtp,
+    #[serde(default)]
+    order: TwoFasOrder,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFasOtp {
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    account: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default = "default_digits")]
+    digits: usize,
+    #[serde(default = "default_period")]
+    period: u64,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    #[serde(default = "default_kind")]
+    token_type: String,
+    #[serde(default)]
+    source: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct TwoFasOrder {
+    position: usize,
+}
+
+fn default_algorithm() -> String {
+    "SHA1".to_string()
+}
+
+fn default_digits() -> usize {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+fn default_kind() -> String {
+    "TOTP".to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Aegis wants one for every entry and slot
+fn uuid() -> String {
+    let mut bytes = random::<16>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex(&bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Builds a code from the bits every format has, `Ok(None)` for ones that aren't TOTP
+fn entry_code(kind: &str, issuer: &str, account: &str, secret: &str, algorithm: &str, digits: usize, period: u64) -> Result<Option<TotpCode>, BackupError> {
+    if !kind.eq_ignore_ascii_case("totp") {
+        return Ok(None);
+    }
+    let mut code = TotpCode::new();
+    let name = otpauth::join_name(issuer, account).unwrap_or_else(|| code.name.clone());
+
+    let bad = |err: UriError| BackupError::Entry(name.clone(), err);
+    code.data.algorithm = otpauth::parse_algorithm(algorithm).ok_or_else(|| bad(UriError::BadParameter("algorithm".to_string())))?;
+    code.data.digits = Some(digits).filter(|digits| (4..=8).contains(digits)).ok_or_else(|| bad(UriError::BadParameter("digits".to_string())))?;
+    code.data.step = Some(period).filter(|&period| period != 0).ok_or_else(|| bad(UriError::BadParameter("period".to_string())))?;
+    code.set_secret_bytes(otpauth::decode_secret(secret).map_err(bad)?);
+    code.name = name;
+    Ok(Some(code))
+}
+
+fn add(imported: &mut Imported, code: Result<Option<TotpCode>, BackupError>, name: String) -> Result<(), BackupError> {
+    match code? {
+        Some(code) => imported.codes.push(code),
+        None => imported.skipped.push(name),
+    }
+    Ok(())
+}
+
+/// Reads any of the formats, telling them apart by their shape. The password is only for encrypted Aegis vaults
+pub fn import(path: &Path, password: Option<&str>) -> Result<Imported, BackupError> {
+    let mut bytes = fs::read(path).map_err(BackupError::Read)?;
+    let json = serde_json::from_slice::<Value>(&bytes);
+    bytes.zeroize();
+    let mut json = json.map_err(BackupError::Json)?;
+
+    let mut imported = Imported { codes: Vec::new(), skipped: Vec::new() };
+    let result = match &json {
+        Value::Object(object) if object.contains_key("header") && object.contains_key("db") => import_aegis(&json, password, &mut imported),
+        Value::Object(object) if object.contains_key("servicesEncrypted") => Err(BackupError::EncryptedUnsupported("2FAS")),
+        Value::Object(object) if object.contains_key("services") => import_two_fas(&json, &mut imported),
+        Value::Array(_) => import_and_otp(&json, &mut imported),
+        _ => Err(BackupError::UnknownFormat),
+    };
+    wipe(&mut json);
+    result.map(|_| imported)
+}
+
+/// serde_json's values don't zeroize themselves, and these are full of secrets
+fn wipe(value: &mut Value) {
+    match value {
+        Value::String(string) => string.zeroize(),
+        Value::Array(array) => array.iter_mut().for_each(wipe),
+        Value::Object(object) => object.values_mut().for_each(wipe),
+        _ => {},
+    }
+}
+
+fn import_aegis(json: &Value, password: Option<&str>, imported: &mut Imported) -> Result<(), BackupError> {
+    let file = AegisFile::deserialize(json).map_err(BackupError::Json)?;
+    let db: AegisDb = match (&file.db, file.header.slots, file.header.params) {
+        (Value::Object(_), _, _) => AegisDb::deserialize(&file.db).map_err(BackupError::Json)?,
+        (Value::String(sealed), Some(slots), Some(params)) => {
+            let password = password.ok_or(BackupError::PasswordRequired)?;
+            let mut master_key = open_slots(&slots, password)?;
+            let mut db = Base64::decode_vec(sealed).map_err(|_| BackupError::BadVault)?;
+            let opened = open(&master_key, &params, &mut db);
+            master_key.zeroize();
+            let parsed = opened.and_then(|_| serde_json::from_slice(&db).map_err(BackupError::Json));
+            db.zeroize();
+            parsed?
+        },
+        _ => return Err(BackupError::BadVault),
+    };
+
+    for mut entry in db.entries {
+        let code = entry_code(&entry.kind, &entry.issuer, &entry.name, &entry.info.secret, &entry.info.algo, entry.info.digits, entry.info.period);
+        entry.info.secret.zeroize();
+        add(imported, code, otpauth::join_name(&entry.issuer, &entry.name).unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+fn import_and_otp(json: &Value, imported: &mut Imported) -> Result<(), BackupError> {
+    for mut entry in Vec::<AndOtpEntry>::deserialize(json).map_err(BackupError::Json)? {
+        // Old versions only had the label, as `Issuer - account`
+        let (issuer, label) = match entry.label.split_once(" - ") {
+            Some((issuer, label)) if entry.issuer.is_empty() => (issuer, label),
+            _ => (entry.issuer.as_str(), entry.label.as_str()),
+        };
+        let code = entry_code(&entry.kind, issuer, label, &entry.secret, &entry.algorithm, entry.digits, entry.period);
+        let name = otpauth::join_name(issuer, label).unwrap_or_default();
+        entry.secret.zeroize();
+        add(imported, code, name)?;
+    }
+    Ok(())
+}
+
+fn import_two_fas(json: &Value, imported: &mut Imported) -> Result<(), BackupError> {
+    let mut services = TwoFasFile::deserialize(json).map_err(BackupError::Json)?.services;
+    services.sort_by_key(|service| service.order.position);
+    for mut service in services {
+        let account = if service.otp.account.is_empty() { &service.otp.label } else { &service.otp.account };
+        // The service name stands in for a missing issuer, unless it's just the account again like ours are then
+        let issuer = match service.otp.issuer.as_str() {
+            "" if service.name == *account => "",
+            "" => &service.name,
+            issuer => issuer,
+        };
+        let code = entry_code(&service.otp.token_type, issuer, account, &service.secret, &service.otp.algorithm, service.otp.digits, service.otp.period);
+        let name = otpauth::join_name(issuer, account).unwrap_or_default();
+        service.secret.zeroize();
+        add(imported, code, name)?;
+    }
+    Ok(())
+}
+
+fn scrypt_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<[u8; KEY_LEN], BackupError> {
+    let params = scrypt::Params::new(log_n, r, p, KEY_LEN).map_err(|_| BackupError::BadVault)?;
+    let mut key = [0u8; KEY_LEN];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key).map_err(|_| BackupError::BadVault)?;
+    Ok(key)
+}
+
+/// Decrypts `data` in place, `WrongPassword` if the tag doesn't match
+fn open(key: &[u8], params: &AegisParams, data: &mut [u8]) -> Result<(), BackupError> {
+    let nonce = unhex(&params.nonce).filter(|nonce| nonce.len() == NONCE_LEN).ok_or(BackupError::BadVault)?;
+    let tag = unhex(&params.tag).filter(|tag| tag.len() == TAG_LEN).ok_or(BackupError::BadVault)?;
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| BackupError::BadVault)?;
+    cipher.decrypt_in_place_detached(Nonce::from_slice(&nonce), b"", data, Tag::from_slice(&tag))
+        .map_err(|_| BackupError::WrongPassword)
+}
+
+fn seal(key: &[u8], data: &mut [u8]) -> AegisParams {
+    let nonce = random::<NONCE_LEN>();
+    let cipher = Aes256Gcm::new_from_slice(key).unwrap();
+    let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", data).unwrap();
+    AegisParams { nonce: hex(&nonce), tag: hex(&tag) }
+}
+
+/// Master key from the first password slot the password opens
+fn open_slots(slots: &[AegisSlot], password: &str) -> Result<Vec<u8>, BackupError> {
+    let mut tried = false;
+    for slot in slots.iter().filter(|slot| slot.kind == 1) {
+        let (Some(n), Some(r), Some(p), Some(salt)) = (slot.n, slot.r, slot.p, slot.salt.as_deref().and_then(unhex)) else { continue };
+        if !n.is_power_of_two() || n.trailing_zeros() > SCRYPT_MAX_LOG_N as u32 {
+            return Err(BackupError::BadVault);
+        }
+        tried = true;
+
+        let mut key = scrypt_key(password, &salt, n.trailing_zeros() as u8, r, p)?;
+        let mut master_key = unhex(&slot.key).ok_or(BackupError::BadVault)?;
+        let opened = open(&key, &slot.key_params, &mut master_key);
+        key.zeroize();
+        match opened {
+            Ok(()) => return Ok(master_key),
+            Err(BackupError::WrongPassword) => master_key.zeroize(),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(if tried { BackupError::WrongPassword } else { BackupError::BadVault })
+}
+
+fn aegis_entry(code: &TotpCode) -> AegisEntry {
+    let (issuer, account) = otpauth::split_name(&code.name);
+    AegisEntry {
+        kind: "totp".to_string(), uuid: uuid(), name: account.to_string(), issuer: issuer.to_string(),
+        note: String::new(), favorite: false, icon: None,
+        info: AegisInfo {
+            secret: code.data.get_secret_base32(), algo: otpauth::algorithm_name(code.data.algorithm).to_string(),
+            digits: code.data.digits, period: code.data.step,
+        },
+    }
+}
+
+fn export_aegis(codes: &[&TotpCode], password: Option<&str>) -> Result<SecretString, BackupError> {
+    let mut db = AegisDb { version: 2, entries: codes.iter().map(|code| aegis_entry(code)).collect() };
+    let db_value = serde_json::to_value(&db).unwrap();
+    db.entries.iter_mut().for_each(|entry| entry.info.secret.zeroize());
+
+    let mut file = match password {
+        None => AegisFile { version: 1, header: AegisHeader { slots: None, params: None }, db: db_value },
+        Some(password) => {
+            let mut db_value = db_value;
+            let mut db_json = serde_json::to_vec(&db_value).unwrap();
+            wipe(&mut db_value);
+            let mut master_key = random::<KEY_LEN>();
+            let params = seal(&master_key, &mut db_json);
+            let db = Value::String(Base64::encode_string(&db_json));
+
+            let salt = random::<KEY_LEN>();
+            let key = scrypt_key(password, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P);
+            let mut sealed_key = master_key.to_vec();
+            master_key.zeroize();
+            let mut key = key?;
+            let key_params = seal(&key, &mut sealed_key);
+            key.zeroize();
+
+            let slot = AegisSlot {
+                kind: 1, uuid: uuid(), key: hex(&sealed_key), key_params,
+                n: Some(1 << SCRYPT_LOG_N), r: Some(SCRYPT_R), p: Some(SCRYPT_P), salt: Some(hex(&salt)),
+                repaired: Some(true), is_backup: Some(false),
+            };
+            AegisFile { version: 1, header: AegisHeader { slots: Some(vec![slot]), params: Some(params) }, db }
+        },
+    };
+    let json = to_json(&file);
+    wipe(&mut file.db);
+    Ok(json)
+}
+
+fn export_and_otp(codes: &[&TotpCode]) -> SecretString {
+    let mut entries: Vec<AndOtpEntry> = codes.iter().map(|code| {
+        let (issuer, account) = otpauth::split_name(&code.name);
+        AndOtpEntry {
+            secret: code.data.get_secret_base32(), issuer: issuer.to_string(), label: account.to_string(),
+            digits: code.data.digits, kind: default_kind(), algorithm: otpauth::algorithm_name(code.data.algorithm).to_string(),
+            period: code.data.step, thumbnail: "Default".to_string(), last_used: code.used * 1000, used_frequency: 0, tags: Vec::new(),
+        }
+    }).collect();
+    let json = to_json(&entries);
+    entries.iter_mut().for_each(|entry| entry.secret.zeroize());
+    json
+}
+
+fn export_two_fas(codes: &[&TotpCode]) -> SecretString {
+    let services = codes.iter().enumerate().map(|(position, code)| {
+        let (issuer, account) = otpauth::split_name(&code.name);
+        TwoFasService {
+            name: if issuer.is_empty() { account.to_string() } else { issuer.to_string() },
+            secret: code.data.get_secret_base32(), updated_at: code.modified * 1000,
+            otp: TwoFasOtp {
+                label: account.to_string(), account: account.to_string(), issuer: issuer.to_string(),
+                digits: code.data.digits, period: code.data.step, algorithm: otpauth::algorithm_name(code.data.algorithm).to_string(),
+                token_type: default_kind(), source: "Manual".to_string(),
+            },
+            order: TwoFasOrder { position },
+        }
+    }).collect();
+    let mut file = TwoFasFile {
+        services, services_encrypted: None, groups: Vec::new(), updated_at: super::stamp::now() * 1000,
+        schema_version: 4, app_version_code: 0, app_version_name: "passrs".to_string(), app_origin: "passrs".to_string(),
+    };
+    let json = to_json(&file);
+    file.services.iter_mut().for_each(|service| service.secret.zeroize());
+    json
+}
+
+/// Sized up front so growing doesn't leave copies behind
+fn to_json<T: Serialize>(value: &T) -> SecretString {
+    let mut json = serde_json::to_vec_pretty(value).unwrap();
+    let mut output = SecretString::with_capacity(json.len() + 1);
+    output.push_str(std::str::from_utf8(&json).unwrap());
+    output.push('\n');
+    json.zeroize();
+    output
+}
+
+/// Writes the file only readable by us, the password is for `aegis-encrypted`.
+/// Codes without a secret are left out, and their names given back.
+pub fn export(codes: &[TotpCode], format: Format, password: Option<&str>, path: &Path) -> Result<Vec<String>, BackupError> {
+    let (codes, skipped): (Vec<&TotpCode>, Vec<&TotpCode>) = codes.iter().partition(|code| !code.data.secret.is_empty());
+    let json = match format {
+        Format::Aegis => export_aegis(&codes, None)?,
+        Format::AegisEncrypted => export_aegis(&codes, Some(password.ok_or(BackupError::PasswordRequired)?))?,
+        Format::AndOtp => export_and_otp(&codes),
+        Format::TwoFas => export_two_fas(&codes),
+    };
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path).map_err(BackupError::Write)?;
+    file.write_all(json.as_bytes()).map_err(BackupError::Write)?;
+    Ok(skipped.into_iter().map(|code| code.name.clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ os::unix::fs::PermissionsExt, path::PathBuf, process };
+    use totp_rs::Algorithm;
+
+    /// Made with Python's scrypt and AES-GCM, password `test`, with one SHA-256 code and one HOTP code
+    const AEGIS_ENCRYPTED: &str = r#"{"version":1,"header":{"slots":[{"type":1,"uuid":"z","key":"6cdcf32b051c4e5df3d072c2ad407d60d6590a852751df5ebacac89f159d5b17","key_params":{"nonce":"000000000000000000000000","tag":"c0082d771d72b3d85ce571c05402f3a2"},"n":1024,"r":8,"p":1,"salt":"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f","repaired":true,"is_backup":false}],"params":{"nonce":"010101010101010101010101","tag":"be97fc1c9f76e1edc91d6bfcd522033e"}},"db":"DQccuDuSyBFOnW0dc92TXwOLWGPYoa5u15yK/6VguQNbgD+JnV7l+XSWNxmWe7jZKtIZwp01m/CV1dqrbYM9zk5B7pSdeQi9J+8Hl5eDyuUqL7nNj3oKz0Fte31R9sHBMHyKJTICgAuF4cUpGXnXvVm7+6z+gNga5quG16wefppIoJZC3frz+t3Ff07dVVSxqGH/ir9q7cU3pty0Yh4DQPLUNP/va7ZzgGSQzAu05xoYPjRx3O9zhrBUrJIGsiv++FcT2mco9GdlP8oZTHic02PRlQvokVIINP4nfoqbLoMFXU5gDwbhXzfXwEVzecmGBgXnwuiODloPt5OdueHKmYbn2cZk+IYzzDbZAn5KDMPLKsXNkcFVpJ/aaDnzceM420q+ateQwJcJxOQm5oo87VtmJiQK5x0SqwERd0INQWkTTpU2yi61HH30cJWk+r8OaNd6nhANkWtCtOez7IZkHY+cLW89IeF4LdF+jMuynwK7rXVvvx719MZ8vNYjdd8="}"#;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("passrs-backup-test-{}-{}", process::id(), name))
+    }
+
+    fn import_json(name: &str, json: &str, password: Option<&str>) -> Result<Imported, BackupError> {
+        let path = temp_path(name);
+        fs::write(&path, json).unwrap();
+        let imported = import(&path, password);
+        fs::remove_file(&path).unwrap();
+        imported
+    }
+
+    fn code(name: &str, secret: &[u8], algorithm: Algorithm, digits: usize, period: u64) -> TotpCode {
+        let mut code = TotpCode::new();
+        code.name = name.to_string();
+        code.set_secret_bytes(secret.to_vec());
+        (code.data.algorithm, code.data.digits, code.data.step) = (algorithm, digits, period);
+        code
+    }
+
+    fn assert_same(imported: &[TotpCode], codes: &[TotpCode]) {
+        assert_eq!(imported.len(), codes.len());
+        for (imported, code) in imported.iter().zip(codes) {
+            assert_eq!(imported.name, code.name);
+            assert!(imported.same_code(code), "`{}` changed", code.name);
+        }
+    }
+
+    fn round_trip(format: Format, password: Option<&str>) {
+        let codes = [
+            code("ACME:alice@example.com", b"Hello!\xde\xad\xbe\xef", Algorithm::SHA256, 8, 60),
+            code("Plain", b"12345678901234567890", Algorithm::SHA1, 6, 30),
+            code("Work:bob", b"abcdefghij", Algorithm::SHA512, 7, 15),
+        ];
+        let path = temp_path("round-trip.json");
+        let skipped = export(&codes, format, password, &path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let imported = import(&path, password);
+        fs::remove_file(&path).unwrap();
+
+        assert!(skipped.is_empty());
+        assert_eq!(mode & 0o777, 0o600);
+        let imported = imported.unwrap();
+        assert!(imported.skipped.is_empty());
+        assert_same(&imported.codes, &codes);
+    }
+
+    #[test]
+    fn aegis_round_trip() {
+        round_trip(Format::Aegis, None);
+    }
+
+    #[test]
+    fn aegis_encrypted_round_trip() {
+        round_trip(Format::AegisEncrypted, Some("correct horse"));
+    }
+
+    #[test]
+    fn and_otp_round_trip() {
+        round_trip(Format::AndOtp, None);
+    }
+
+    #[test]
+    fn two_fas_round_trip() {
+        round_trip(Format::TwoFas, None);
+    }
+
+    #[test]
+    fn aegis_encrypted_vector() {
+        let imported = import_json("vector.json", AEGIS_ENCRYPTED, Some("test")).unwrap();
+        assert_same(&imported.codes, &[code("ACME:alice@example.com", b"Hello!\xde\xad\xbe\xef", Algorithm::SHA256, 8, 60)]);
+        assert_eq!(imported.skipped, ["Bank:counter"]);
+    }
+
+    #[test]
+    fn wrong_password() {
+        assert!(matches!(import_json("wrong.json", AEGIS_ENCRYPTED, Some("tset")), Err(BackupError::WrongPassword)));
+        assert!(matches!(import_json("none.json", AEGIS_ENCRYPTED, None), Err(BackupError::PasswordRequired)));
+
+        // A damaged vault isn't the password's fault
+        let damaged = AEGIS_ENCRYPTED.replace("\"n\":1024", "\"n\":1000");
+        assert!(matches!(import_json("damaged.json", &damaged, Some("test")), Err(BackupError::BadVault)));
+    }
+
+    #[test]
+    fn hotp_skipped() {
+        let and_otp = r#"[
+            {"secret": "JBSWY3DPEHPK3PXP", "issuer": "ACME", "label": "alice", "type": "TOTP"},
+            {"secret": "GEZDGNBVGY3TQOJQ", "issuer": "Bank", "label": "counter", "type": "HOTP", "counter": 3},
+            {"secret": "GEZDGNBVGY3TQOJQ", "label": "Steam - gabe", "type": "STEAM"}
+        ]"#;
+        let imported = import_json("andotp.json", and_otp, None).unwrap();
+        assert_eq!(imported.codes.len(), 1);
+        assert_eq!(imported.codes[0].name, "ACME:alice");
+        assert_eq!(imported.skipped, ["Bank:counter", "Steam:gabe"]);
+
+        let two_fas = r#"{"services": [
+            {"name": "Bank", "secret": "GEZDGNBVGY3TQOJQ", "otp": {"account": "counter", "tokenType": "HOTP"}, "order": {"position": 1}},
+            {"name": "ACME", "secret": "JBSWY3DPEHPK3PXP", "otp": {"account": "alice"}, "order": {"position": 0}}
+        ], "schemaVersion": 4}"#;
+        let imported = import_json("2fas.json", two_fas, None).unwrap();
+        assert_eq!(imported.codes.len(), 1);
+        assert_eq!(imported.codes[0].name, "ACME:alice");
+        assert_eq!(imported.skipped, ["Bank:counter"]);
+    }
+
+    #[test]
+    fn secretless_codes_skipped() {
+        let mut blank = TotpCode::new();
+        blank.name = "Blank".to_string();
+        let codes = [code("ACME:alice", b"Hello!\xde\xad\xbe\xef", Algorithm::SHA1, 6, 30), blank];
+
+        let path = temp_path("secretless.json");
+        let skipped = export(&codes, Format::AndOtp, None, &path).unwrap();
+        let imported = import(&path, None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(skipped, ["Blank"]);
+        assert_same(&imported.unwrap().codes, &codes[..1]);
+    }
+
+    #[test]
+    fn not_a_backup() {
+        assert!(matches!(import_json("object.json", r#"{"entries": []}"#, None), Err(BackupError::UnknownFormat)));
+        assert!(matches!(import_json("broken.json", "[{", None), Err(BackupError::Json(_))));
+        assert!(matches!(import_json("2fas-encrypted.json", r#"{"services": [], "servicesEncrypted": "abc"}"#, None), Err(BackupError::EncryptedUnsupported("2FAS"))));
+    }
+}
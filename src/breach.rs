@@ -0,0 +1,206 @@
+use std::{ cmp::Ordering, fs::File, io::{ self, BufRead, BufReader, Read, Seek, SeekFrom }, path::{ Path, PathBuf } };
+use sha1::{ Digest, Sha1 };
+use zeroize::Zeroize;
+
+// Have I Been Pwned's breached passwords, as SHA-1 hashes with how often each was seen. Either
+//     one big file of `HASH:COUNT` lines sorted by hash, searched in place so its size doesn't matter
+//     or a directory of range files named by the first 5 characters of the hash, holding `REST:COUNT` lines
+// Passwords are only ever hashed in memory.
+
+const HASH_LEN: usize = 40;
+const PREFIX_LEN: usize = 5;
+
+pub enum Dump {
+    Sorted { file: File, len: u64 },
+    Ranges(PathBuf),
+}
+
+impl Dump {
+    pub fn open(path: &Path) -> io::Result<Dump> {
+        if path.is_dir() {
+            return Ok(Dump::Ranges(path.to_path_buf()));
+        }
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Dump::Sorted { file, len })
+    }
+
+    /// How many times the password turns up in breaches, `None` if it doesn't
+    pub fn count(&mut self, password: &str) -> io::Result<Option<u64>> {
+        let mut hash = hex_upper(&Sha1::digest(password.as_bytes()));
+        let count = match self {
+            Dump::Sorted { file, len } => search_sorted(file, *len, &hash),
+            Dump::Ranges(dir) => search_range(dir, &hash),
+        };
+        hash.zeroize();
+        count
+    }
+}
+
+fn hex_upper(bytes: &[u8]) -> [u8; HASH_LEN] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut hex = [0u8; HASH_LEN];
+    for (index, byte) in bytes.iter().enumerate() {
+        hex[index * 2] = DIGITS[(byte >> 4) as usize];
+        hex[index * 2 + 1] = DIGITS[(byte & 0xf) as usize];
+    }
+    hex
+}
+
+/// Count from the end of a `HASH:COUNT` line, which may have a `\r\n` ending
+fn line_count(line: &[u8]) -> Option<u64> {
+    let count = &line[line.iter().position(|&b| b == b':')? + 1..];
+    std::str::from_utf8(count).ok()?.trim_end().parse().ok()
+}
+
+/// First whole line starting at or after `pos`, and where it starts
+fn line_from(file: &mut File, pos: u64, line: &mut Vec<u8>) -> io::Result<Option<u64>> {
+    // Starting one byte early means a line that starts right at `pos` isn't skipped
+    let seek_to = pos.saturating_sub(1);
+    file.seek(SeekFrom::Start(seek_to))?;
+    let mut reader = BufReader::with_capacity(256, file);
+
+    let mut start = seek_to;
+    line.clear();
+    if pos != 0 {
+        start += reader.read_until(b'\n', line)? as u64;
+        line.clear();
+    }
+    if reader.read_until(b'\n', line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(start))
+}
+
+/// Binary search on byte offsets, keeping the wanted line's start somewhere in `low..high`
+fn search_sorted(file: &mut File, len: u64, hash: &[u8; HASH_LEN]) -> io::Result<Option<u64>> {
+    let (mut low, mut high) = (0, len);
+    let mut line = Vec::with_capacity(64);
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let Some(start) = line_from(file, mid, &mut line)? else {
+            high = mid;
+            continue;
+        };
+        if line.len() < HASH_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a list of SHA-1 hashes"));
+        }
+
+        match hash[..].cmp(&line[..HASH_LEN].to_ascii_uppercase()) {
+            Ordering::Equal => return Ok(line_count(&line)),
+            Ordering::Less => high = mid,
+            Ordering::Greater => low = start + line.len() as u64,
+        }
+    }
+    Ok(None)
+}
+
+fn search_range(dir: &Path, hash: &[u8; HASH_LEN]) -> io::Result<Option<u64>> {
+    let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+    let prefix = std::str::from_utf8(prefix).unwrap();
+    let path = [dir.join(prefix), dir.join(format!("{}.txt", prefix))].into_iter()
+        .find(|path| path.exists())
+        // Naming the missing file would give away the start of the password's hash
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("range files missing from `{}`", dir.display())))?;
+
+    let mut contents = Vec::new();
+    File::open(path)?.read_to_end(&mut contents)?;
+    Ok(contents.split(|&b| b == b'\n')
+        .find(|line| line.len() > suffix.len() && line[..suffix.len()].eq_ignore_ascii_case(suffix))
+        .and_then(line_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn passwords() -> Vec<(String, u64)> {
+        (0..200).map(|n| (format!("password{}", n), n + 1)).collect()
+    }
+
+    /// A sorted dump of `passwords()`, with `ending` after every line
+    fn write_sorted(name: &str, ending: &str) -> PathBuf {
+        let mut lines: Vec<_> = passwords().into_iter()
+            .map(|(password, count)| (hex_upper(&Sha1::digest(password.as_bytes())), count))
+            .collect();
+        lines.sort();
+        let text: String = lines.iter()
+            .map(|(hash, count)| format!("{}:{}{}", std::str::from_utf8(hash).unwrap(), count, ending))
+            .collect();
+
+        let path = std::env::temp_dir().join(format!("passrs-test-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn check_sorted(path: &Path) {
+        let mut dump = Dump::open(path).unwrap();
+        for (password, count) in passwords() {
+            assert_eq!(dump.count(&password).unwrap(), Some(count), "{}", password);
+        }
+        assert_eq!(dump.count("not in there").unwrap(), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sorted() {
+        check_sorted(&write_sorted("lf", "\n"));
+    }
+
+    #[test]
+    fn sorted_crlf() {
+        check_sorted(&write_sorted("crlf", "\r\n"));
+    }
+
+    #[test]
+    fn sorted_first_and_last() {
+        let path = write_sorted("ends", "\n");
+        let text = fs::read_to_string(&path).unwrap();
+        let (first, last) = (text.lines().next().unwrap(), text.lines().last().unwrap());
+
+        let mut file = File::open(&path).unwrap();
+        let len = text.len() as u64;
+        for line in [first, last] {
+            let hash: [u8; HASH_LEN] = line.as_bytes()[..HASH_LEN].try_into().unwrap();
+            assert_eq!(search_sorted(&mut file, len, &hash).unwrap(), line_count(line.as_bytes()));
+        }
+        // Either side of everything in the file
+        assert_eq!(search_sorted(&mut file, len, &[b'0'; HASH_LEN]).unwrap(), None);
+        assert_eq!(search_sorted(&mut file, len, &[b'F'; HASH_LEN]).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lowercase_and_unterminated() {
+        let path = std::env::temp_dir().join(format!("passrs-test-{}-lower", std::process::id()));
+        let hash = hex_upper(&Sha1::digest(b"hunter2"));
+        fs::write(&path, format!("{}:17", std::str::from_utf8(&hash).unwrap().to_ascii_lowercase())).unwrap();
+        assert_eq!(Dump::open(&path).unwrap().count("hunter2").unwrap(), Some(17));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_hashes() {
+        let path = std::env::temp_dir().join(format!("passrs-test-{}-junk", std::process::id()));
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        assert!(Dump::open(&path).unwrap().count("two").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ranges() {
+        let dir = std::env::temp_dir().join(format!("passrs-test-{}-ranges", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hash = hex_upper(&Sha1::digest(b"hunter2"));
+        let (prefix, suffix) = std::str::from_utf8(&hash).unwrap().split_at(PREFIX_LEN);
+        fs::write(dir.join(format!("{}.txt", prefix)), format!("0000000000000000000000000000000000A:1\r\n{}:42\r\n", suffix)).unwrap();
+
+        let mut dump = Dump::open(&dir).unwrap();
+        assert_eq!(dump.count("hunter2").unwrap(), Some(42));
+        // A password whose range file is missing is an error, not a clean bill of health
+        assert_eq!(dump.count("not in there").unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
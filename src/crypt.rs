@@ -0,0 +1,220 @@
+use argon2::{Argon2, Algorithm, Version, Params};
+use orion::aead::{open, seal, SecretKey};
+use rand::{thread_rng, RngCore};
+use std::{ cmp::min, fs, io };
+use zeroize::Zeroize;
+use super::secret::SecretString;
+
+pub const SALT_LEN: usize = 16;
+
+// Anything above 4GiB is more likely a corrupt header than a paranoid user
+const MAX_MEM_KIB: u32 = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KdfParams {
+    pub mem_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> KdfParams {
+        KdfParams { mem_kib: 64 * 1024, iterations: 3, parallelism: 1 }
+    }
+}
+
+impl KdfParams {
+    pub fn argon2_params(&self) -> Result<Params, argon2::Error> {
+        if self.mem_kib > MAX_MEM_KIB {
+            return Err(argon2::Error::MemoryTooMuch);
+        }
+        Params::new(self.mem_kib, self.iterations, self.parallelism, Some(32))
+    }
+}
+
+/// Hash of a keyfile's contents, so any file (of any size) can be used
+pub struct Keyfile([u8; 32]);
+
+impl Keyfile {
+    pub fn read(path: &str) -> io::Result<Keyfile> {
+        let mut contents = fs::read(path)?;
+        let hashed = orion::hash::digest(&contents);
+        contents.zeroize();
+
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(hashed.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "keyfile is empty"))?.as_ref());
+        Ok(Keyfile(hash))
+    }
+}
+
+impl Drop for Keyfile {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Whatever the user unlocks the data file with, a password, a keyfile, or both
+#[derive(Default)]
+pub struct Credentials {
+    pub password: Option<SecretString>,
+    pub keyfile: Option<Keyfile>,
+}
+
+impl Credentials {
+    pub fn is_empty(&self) -> bool {
+        self.password.is_none() && self.keyfile.is_none()
+    }
+}
+
+/// One way of unlocking the data file - the data key, sealed with a key derived from some credentials
+#[derive(Clone)]
+pub struct KeySlot {
+    pub label: String,
+    pub params: KdfParams,
+    pub salt: [u8; SALT_LEN],
+    pub password: bool,
+    pub keyfile: bool,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl KeySlot {
+    fn new(label: String, creds: &Credentials, params: KdfParams, data_key: &SecretKey) -> Result<KeySlot, argon2::Error> {
+        let mut salt = [0u8; SALT_LEN];
+        thread_rng().fill_bytes(&mut salt);
+        let slot_key = derive_key(creds, salt, params)?;
+
+        Ok(KeySlot {
+            label, params, salt,
+            password: creds.password.is_some(),
+            keyfile: creds.keyfile.is_some(),
+            wrapped_key: seal(&slot_key, data_key.unprotected_as_bytes()).unwrap(),
+        })
+    }
+
+    /// Whether the slot takes the same kind of credentials, not whether they're right
+    pub fn accepts(&self, creds: &Credentials) -> bool {
+        self.password == creds.password.is_some() && self.keyfile == creds.keyfile.is_some()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match (self.password, self.keyfile) {
+            (true, true) => "password+keyfile",
+            (true, false) => "password",
+            (false, _) => "keyfile",
+        }
+    }
+
+    fn unwrap_key(&self, creds: &Credentials) -> Result<Option<SecretKey>, argon2::Error> {
+        let slot_key = derive_key(creds, self.salt, self.params)?;
+        Ok(open(&slot_key, &self.wrapped_key).ok().map(|mut key_bytes| {
+            let key = SecretKey::from_slice(&key_bytes).unwrap();
+            key_bytes.zeroize();
+            key
+        }))
+    }
+}
+
+/// Random key the data is actually sealed with, so key slots can come and go without re-encrypting it
+pub struct MasterKey {
+    data_key: SecretKey,
+    pub slots: Vec<KeySlot>,
+    /// The slot this was unlocked with, which `p` changes
+    pub slot: usize,
+    /// Salts of slots revoked or replaced since unlocking, which backups mustn't keep either
+    pub revoked: Vec<[u8; SALT_LEN]>,
+}
+
+impl MasterKey {
+    pub fn new(creds: &Credentials, params: KdfParams) -> Result<MasterKey, argon2::Error> {
+        let data_key = SecretKey::generate(32).unwrap();
+        let slot = KeySlot::new("default".to_string(), creds, params, &data_key)?;
+        Ok(MasterKey { data_key, slots: vec![slot], slot: 0, revoked: Vec::new() })
+    }
+
+    /// Tries every slot that takes these kinds of credentials, `None` if none of them open
+    pub fn unlock(slots: Vec<KeySlot>, creds: &Credentials) -> Result<Option<MasterKey>, argon2::Error> {
+        for (index, slot) in slots.iter().enumerate() {
+            if !slot.accepts(creds) {
+                continue;
+            }
+            if let Some(data_key) = slot.unwrap_key(creds)? {
+                return Ok(Some(MasterKey { data_key, slots, slot: index, revoked: Vec::new() }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Key cached by the keyring, which can open the data but has no slots to save it with
+    pub fn from_data_key(data_key: &[u8]) -> Option<MasterKey> {
+        Some(MasterKey { data_key: SecretKey::from_slice(data_key).ok()?, slots: Vec::new(), slot: 0, revoked: Vec::new() })
+    }
+
+    pub fn data_key(&self) -> &[u8] {
+        self.data_key.unprotected_as_bytes()
+    }
+
+    /// Whether any slot still opens with these credentials, for unlocking the UI again
+    pub fn check(&self, creds: &Credentials) -> bool {
+        self.slots.iter()
+            .filter(|slot| slot.accepts(creds))
+            .any(|slot| matches!(slot.unwrap_key(creds), Ok(Some(key)) if key == self.data_key))
+    }
+
+    pub fn add_slot(&mut self, label: String, creds: &Credentials, params: KdfParams) -> Result<(), argon2::Error> {
+        let slot = KeySlot::new(label, creds, params, &self.data_key)?;
+        self.slots.push(slot);
+        Ok(())
+    }
+
+    /// Swaps the credentials of the slot this was unlocked with, leaving the others alone
+    pub fn replace_slot(&mut self, creds: &Credentials, params: KdfParams) -> Result<(), argon2::Error> {
+        let label = self.slots[self.slot].label.clone();
+        let old_slot = std::mem::replace(&mut self.slots[self.slot], KeySlot::new(label, creds, params, &self.data_key)?);
+        self.revoked.push(old_slot.salt);
+        Ok(())
+    }
+
+    pub fn revoke_slot(&mut self, index: usize) {
+        let slot = self.slots.remove(index);
+        self.revoked.push(slot.salt);
+        if self.slot > index {
+            self.slot -= 1;
+        }
+    }
+
+    pub fn seal(&self, data: &[u8]) -> Option<Vec<u8>> {
+        seal(&self.data_key, data).ok()
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        open(&self.data_key, sealed).ok()
+    }
+}
+
+fn derive_key(creds: &Credentials, salt: [u8; SALT_LEN], params: KdfParams) -> Result<SecretKey, argon2::Error> {
+    // The keyfile goes in as Argon2's secret input, rather than being mashed together with the password
+    let argon = match creds.keyfile {
+        Some(ref keyfile) => Argon2::new_with_secret(&keyfile.0, Algorithm::Argon2id, Version::V0x13, params.argon2_params()?)?,
+        None => Argon2::new(Algorithm::Argon2id, Version::V0x13, params.argon2_params()?),
+    };
+    let pass = creds.password.as_ref().map_or(&b""[..], |pass| pass.as_bytes());
+
+    let mut key_bytes = [0u8; 32];
+    let hashed = argon.hash_password_into(pass, &salt, &mut key_bytes);
+    let key = SecretKey::from_slice(&key_bytes).unwrap();
+    key_bytes.zeroize();
+    hashed?;
+
+    Ok(key)
+}
+
+/// The original key scheme, password bytes zero-padded or clipped to 32 bytes - only used to open old files
+pub fn legacy_key(pass: &str) -> SecretKey {
+    let mut padded_bytes = [0u8; 32];
+    let pass_len = min(pass.len(), padded_bytes.len());
+    padded_bytes[..pass_len].copy_from_slice(&pass.as_bytes()[..pass_len]);
+
+    let key = SecretKey::from_slice(&padded_bytes).unwrap();
+    padded_bytes.zeroize();
+    key
+}
@@ -0,0 +1,198 @@
+use std::{ fmt, io, process };
+use super::{ gen::ProfileError, vault::FormatError, otpauth::UriError, qr::QrError, authbackup::BackupError };
+
+// None of these hold onto (or print) anything from the data file, only where and how things went wrong
+
+#[derive(Debug)]
+pub enum Error {
+    Load(LoadError),
+    Decrypt(DecryptError),
+    Parse(ParseError),
+    Save(SaveError),
+    Encrypt(EncryptError),
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Read(io::Error),
+    Lock(io::Error),
+    Locked,
+    Format(FormatError),
+    Keyfile(String, io::Error),
+    NoBackup(usize),
+    NoKeySlots,
+    NoSlot(usize),
+    NoItem(String),
+    NoField(String, String),
+    NoHistory(String, usize),
+    Agent(String),
+    AgentSocket(io::Error),
+    Keyring(io::Error),
+    Password(io::Error),
+    Profile(ProfileError),
+    Breaches(io::Error),
+    Uri(UriError),
+    Qr(QrError),
+    Backup(BackupError),
+}
+
+#[derive(Debug)]
+pub enum DecryptError {
+    PrintModeNeedsPassword,
+    PasswordRequired,
+    NotEncrypted,
+    PasswordNotUsed,
+    KeyfileRequired,
+    KeyfileNotUsed,
+    WrongPassword,
+    KdfParams(argon2::Error),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub category: serde_json::error::Category,
+    /// Headerless files might just need a password
+    pub maybe_encrypted: bool,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Write(io::Error),
+    Restore(io::Error),
+    Conflict,
+    Merge(Box<Error>),
+    LastSlot,
+    /// Only ever `QrError::Write`, the QR code itself is made before anything is saved
+    Qr(QrError),
+    /// Only ever `BackupError::Write`
+    Backup(BackupError),
+}
+
+#[derive(Debug)]
+pub enum EncryptError {
+    Seal,
+    KdfParams(argon2::Error),
+    NoNewCredentials,
+    TooManySlots,
+}
+
+impl Error {
+    /// Matches the return codes listed in `--help`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Load(_) | Error::Decrypt(_) | Error::Parse(_) => 1,
+            Error::Save(_) | Error::Encrypt(_) => 2,
+        }
+    }
+
+    pub fn exit(self) -> ! {
+        eprintln!("{}", self);
+        process::exit(self.exit_code());
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Load(err) => match err {
+                LoadError::Read(err) => write!(f, "Cannot read data file: {}", err),
+                LoadError::Lock(err) => write!(f, "Could not lock data file: {}", err),
+                LoadError::Locked => write!(f, "Data file is already open in another passrs"),
+                LoadError::Format(err) => write!(f, "Cannot open data file, {}", err),
+                LoadError::Keyfile(path, err) => write!(f, "Cannot read keyfile `{}`: {}", path, err),
+                LoadError::NoBackup(index) => write!(f, "No backup number {}, see `--list-backups`", index),
+                LoadError::NoKeySlots => write!(f, "Data file has no key slots, open and save it with a password or keyfile first"),
+                LoadError::NoSlot(index) => write!(f, "No key slot number {}, see `--list-slots`", index),
+                LoadError::NoItem(name) => write!(f, "Nothing named `{}`, see `--list`", name),
+                LoadError::NoField(name, key) => write!(f, "`{}` has no field `{}`, see `--show`", name, key),
+                LoadError::NoHistory(name, index) => write!(f, "`{}` has no old password number {}, see `--history`", name, index),
+                LoadError::Agent(message) => write!(f, "passrs agent: {}", message),
+                LoadError::AgentSocket(err) => write!(f, "Cannot start agent: {}", err),
+                LoadError::Keyring(err) => write!(f, "Cannot use kernel keyring: {}", err),
+                LoadError::Password(err) => write!(f, "Cannot get password: {}", err),
+                LoadError::Profile(err) => write!(f, "Cannot generate password, {}", err),
+                LoadError::Breaches(err) => write!(f, "Cannot read breached password list: {}", err),
+                LoadError::Uri(err) => write!(f, "Cannot add TOTP code, {}", err),
+                LoadError::Qr(err) => write!(f, "{}", err),
+                LoadError::Backup(err) => write!(f, "{}", err),
+            },
+            Error::Decrypt(err) => match err {
+                DecryptError::PrintModeNeedsPassword => write!(f, "Print mode and key slot changes require a password or keyfile to be specified with PASSRS_PASS, PASSRS_KEYFILE or `--pinentry` and friends, print mode can also use a running `passrs agent` or a key cached by `--keyring`"),
+                DecryptError::PasswordRequired => write!(f, "Data file is encrypted, a password is required"),
+                DecryptError::NotEncrypted => write!(f, "Data file is not encrypted, open it without a password or keyfile"),
+                DecryptError::PasswordNotUsed => write!(f, "Data file is only locked with a keyfile, leave the password empty"),
+                DecryptError::KeyfileRequired => write!(f, "Data file requires a keyfile, specify it with `--keyfile` or PASSRS_KEYFILE"),
+                DecryptError::KeyfileNotUsed => write!(f, "Data file doesn't use a keyfile, change that with `p` after opening it without one"),
+                DecryptError::WrongPassword => write!(f, "Cannot decrypt data with provided password or keyfile"),
+                DecryptError::KdfParams(err) => write!(f, "Invalid key derivation parameters in data file: {}", err),
+            },
+            Error::Parse(err) => {
+                use serde_json::error::Category;
+                let kind = match err.category {
+                    Category::Io => "could not be read",
+                    Category::Syntax => "is not valid JSON",
+                    Category::Data => "has missing or mistyped fields",
+                    Category::Eof => "ends unexpectedly",
+                };
+                write!(f, "Cannot parse data, it {} at line {} column {}", kind, err.line, err.column)?;
+                if err.maybe_encrypted {
+                    write!(f, ", you might require a password")?;
+                }
+                Ok(())
+            },
+            Error::Save(err) => match err {
+                SaveError::Write(err) => write!(f, "Could not save file: {}", err),
+                SaveError::Restore(err) => write!(f, "Could not restore backup: {}", err),
+                SaveError::Conflict => write!(f, "Data file changed on disk, not saving"),
+                SaveError::Merge(err) => write!(f, "Cannot merge with the changed data file, not saving: {}", err),
+                SaveError::LastSlot => write!(f, "Cannot revoke the only key slot, the data file could never be opened again"),
+                SaveError::Qr(err) => write!(f, "{}", err),
+                SaveError::Backup(err) => write!(f, "{}", err),
+            },
+            Error::Encrypt(err) => match err {
+                EncryptError::Seal => write!(f, "Could not encrypt data"),
+                EncryptError::KdfParams(err) => write!(f, "Invalid key derivation parameters: {}", err),
+                EncryptError::NoNewCredentials => write!(f, "New key slot needs a password in PASSRS_NEW_PASS or a keyfile from `--new-keyfile`"),
+                EncryptError::TooManySlots => write!(f, "Data file already has the most key slots it can hold"),
+            },
+        }
+    }
+}
+
+impl ParseError {
+    pub fn new(err: &serde_json::Error, maybe_encrypted: bool) -> ParseError {
+        ParseError { line: err.line(), column: err.column(), category: err.classify(), maybe_encrypted }
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(err: LoadError) -> Error {
+        Error::Load(err)
+    }
+}
+
+impl From<DecryptError> for Error {
+    fn from(err: DecryptError) -> Error {
+        Error::Decrypt(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<SaveError> for Error {
+    fn from(err: SaveError) -> Error {
+        Error::Save(err)
+    }
+}
+
+impl From<EncryptError> for Error {
+    fn from(err: EncryptError) -> Error {
+        Error::Encrypt(err)
+    }
+}
@@ -0,0 +1,627 @@
+use std::{ cell::OnceCell, env, fmt, ops::RangeInclusive, rc::Rc, sync::OnceLock };
+use rand::{ thread_rng, Rng, seq::SliceRandom };
+use unicode_general_category::{ get_general_category, GeneralCategory };
+use super::secret::SecretString;
+
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+/// Easily mixed up when reading a password off a screen
+const LOOKALIKES: &str = "Il1|O0o`'\"";
+/// Past here it's all unassigned or private use
+const LAST_PRINTABLE: u32 = 0x3134f;
+/// Give up on rules that can't (or can hardly ever) be met
+const MAX_ATTEMPTS: usize = 1000;
+/// EFF's long diceware list, 7776 words numbered by dice rolls
+const WORDLIST: &str = include_str!("eff_large_wordlist.txt");
+
+const BLOCKS: [(&str, RangeInclusive<u32>); 8] = [
+    ("latin1", 0x00a1..=0x00ff),
+    ("greek", 0x0370..=0x03ff),
+    ("cyrillic", 0x0400..=0x04ff),
+    ("hiragana", 0x3040..=0x309f),
+    ("katakana", 0x30a0..=0x30ff),
+    ("cjk", 0x4e00..=0x9fff),
+    ("hangul", 0xac00..=0xd7a3),
+    ("emoji", 0x1f300..=0x1f64f),
+];
+
+/// Built in profiles, the first is the default unless PASSRS_PROFILE picks another
+const BUILTIN: [(&str, &str); 7] = [
+    ("default", "length=24,chars=lower+upper+digits+symbols,require=lower+upper+digits+symbols"),
+    ("alnum", "length=20,chars=lower+upper+digits,require=lower+upper+digits"),
+    ("readable", "length=16,chars=lower+upper+digits,require=lower+upper+digits,no-lookalikes"),
+    ("pin", "length=6,chars=digits"),
+    ("unicode", "length=32,chars=unicode"),
+    ("words", "words=6,separator=-"),
+    ("memorable", "words=5,separator=space,case=title,digits=1"),
+];
+
+#[derive(Clone, PartialEq)]
+enum Class {
+    Lower,
+    Upper,
+    Digits,
+    Symbols,
+    /// Unicode block, by index into `BLOCKS`
+    Block(usize),
+    /// Every assigned, printable codepoint
+    Unicode,
+    /// Characters listed in a password rule, like `[-().&@?'#,/"+]`
+    Custom(Vec<char>),
+}
+
+#[derive(Clone, Copy)]
+enum Case {
+    Lower,
+    Title,
+    Upper,
+    /// Each word title case or not, at random
+    Random,
+}
+
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    /// Diceware passphrase of this many words rather than random characters
+    words: Option<usize>,
+    separator: String,
+    case: Case,
+    /// Digits tacked onto the end of random words
+    digits: usize,
+    length: usize,
+    /// For sites that count bytes rather than characters
+    max_bytes: Option<usize>,
+    classes: Vec<Class>,
+    /// Sets of classes that each need at least one character in the password
+    required: Vec<Vec<Class>>,
+    /// Longest run of the same character
+    max_consecutive: Option<usize>,
+    no_lookalikes: bool,
+    /// Characters it picks from, built on first use since the edit view asks for them every frame
+    pool: OnceCell<Rc<[char]>>,
+}
+
+/// A site's password constraints in Apple's `passwordrules` syntax, e.g.
+/// `minlength: 12; required: upper; required: digit, [-_]; allowed: lower; max-consecutive: 2`
+#[derive(Default)]
+pub struct Rules {
+    /// Each needs at least one character, kept with the rule's own text to say which is missing
+    required: Vec<(String, Vec<Class>)>,
+    allowed: Vec<Class>,
+    max_consecutive: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Unknown(String),
+    BadOption(String),
+    BadRule(String),
+    NoCharacters,
+    Unsatisfiable,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::Unknown(name) => write!(f, "no generator profile called `{}`", name),
+            ProfileError::BadOption(option) => write!(f, "generator profile option `{}` isn't valid", option),
+            ProfileError::BadRule(rule) => write!(f, "password rule `{}` isn't valid", rule),
+            ProfileError::NoCharacters => write!(f, "generator profile has no characters to pick from"),
+            ProfileError::Unsatisfiable => write!(f, "generator profile's length is too short for the characters it requires"),
+        }
+    }
+}
+
+impl Class {
+    fn parse(name: &str) -> Option<Class> {
+        match name {
+            "lower" => Some(Class::Lower),
+            "upper" => Some(Class::Upper),
+            "digits" => Some(Class::Digits),
+            "symbols" => Some(Class::Symbols),
+            "unicode" => Some(Class::Unicode),
+            _ => BLOCKS.iter().position(|(block, _)| *block == name).map(Class::Block),
+        }
+    }
+
+    fn contains(&self, c: char) -> bool {
+        match self {
+            Class::Lower => c.is_ascii_lowercase(),
+            Class::Upper => c.is_ascii_uppercase(),
+            Class::Digits => c.is_ascii_digit(),
+            Class::Symbols => SYMBOLS.contains(c),
+            Class::Block(index) => BLOCKS[*index].1.contains(&(c as u32)) && printable(c),
+            Class::Unicode => printable(c),
+            Class::Custom(chars) => chars.contains(&c),
+        }
+    }
+
+    fn chars(&self) -> Vec<char> {
+        // Checking the category of every codepoint is slow enough to only do once
+        static UNICODE: OnceLock<Vec<char>> = OnceLock::new();
+
+        let range = match self {
+            Class::Custom(chars) => return chars.clone(),
+            Class::Unicode => {
+                return UNICODE.get_or_init(|| (0x21..=LAST_PRINTABLE).filter_map(char::from_u32).filter(|&c| printable(c)).collect()).clone();
+            },
+            Class::Block(index) => BLOCKS[*index].1.clone(),
+            _ => 0x21..=0x7e,
+        };
+        range.filter_map(char::from_u32).filter(|&c| self.contains(c)).collect()
+    }
+}
+
+/// Letters, numbers, punctuation and symbols, not marks that stack onto other characters,
+/// spaces, controls or anything unassigned
+fn printable(c: char) -> bool {
+    use GeneralCategory::*;
+    !matches!(get_general_category(c),
+              NonspacingMark | SpacingMark | EnclosingMark | SpaceSeparator | LineSeparator | ParagraphSeparator |
+              Control | Format | Surrogate | PrivateUse | Unassigned)
+}
+
+impl Profile {
+    /// Options separated by commas, e.g. `length=20,bytes=64,chars=lower+digits,require=digits,no-lookalikes`,
+    /// or for a passphrase `words=6,separator=space,case=title,digits=2`
+    pub fn parse(name: &str, spec: &str) -> Result<Profile, ProfileError> {
+        let mut profile = Profile {
+            name: name.to_string(),
+            words: None, separator: " ".to_string(), case: Case::Lower, digits: 0,
+            length: 24, max_bytes: None, classes: Vec::new(), required: Vec::new(), max_consecutive: None, no_lookalikes: false,
+            pool: OnceCell::new(),
+        };
+        let classes = |list: &str| list.split('+').map(Class::parse).collect::<Option<Vec<_>>>();
+
+        for option in spec.split(',').filter(|option| !option.is_empty()) {
+            let bad_option = || ProfileError::BadOption(option.to_string());
+            match option.split_once('=') {
+                Some(("length", length)) => profile.length = length.parse().map_err(|_| bad_option())?,
+                Some(("bytes", bytes)) => profile.max_bytes = Some(bytes.parse().map_err(|_| bad_option())?),
+                Some(("chars", list)) => profile.classes = classes(list).ok_or_else(bad_option)?,
+                Some(("require", list)) => profile.required = classes(list).ok_or_else(bad_option)?.into_iter().map(|class| vec![class]).collect(),
+                None if option == "no-lookalikes" => profile.no_lookalikes = true,
+                Some(("words", words)) => profile.words = Some(words.parse().map_err(|_| bad_option())?),
+                // Commas separate options, so spaces and nothing get names
+                Some(("separator", "space")) => profile.separator = " ".to_string(),
+                Some(("separator", "none")) => profile.separator = String::new(),
+                Some(("separator", separator)) => profile.separator = separator.to_string(),
+                Some(("case", case)) => profile.case = match case {
+                    "lower" => Case::Lower,
+                    "title" => Case::Title,
+                    "upper" => Case::Upper,
+                    "random" => Case::Random,
+                    _ => return Err(bad_option()),
+                },
+                Some(("digits", digits)) => profile.digits = digits.parse().map_err(|_| bad_option())?,
+                _ => return Err(bad_option()),
+            }
+        }
+
+        // Passphrases don't use any of the character options
+        if let Some(words) = profile.words {
+            return if words == 0 { Err(ProfileError::NoCharacters) } else { Ok(profile) };
+        }
+        if profile.classes.is_empty() {
+            return Err(ProfileError::NoCharacters);
+        }
+        // Required classes have to be ones it picks from too
+        for class in profile.required.concat() {
+            if !profile.classes.contains(&class) {
+                profile.classes.push(class);
+            }
+        }
+        if profile.required.len() > profile.length {
+            return Err(ProfileError::Unsatisfiable);
+        }
+        Ok(profile)
+    }
+
+    pub fn generate(&self) -> Result<SecretString, ProfileError> {
+        if let Some(words) = self.words {
+            return self.passphrase(words);
+        }
+
+        let pool = self.pool();
+        if pool.is_empty() {
+            return Err(ProfileError::NoCharacters);
+        }
+
+        let mut rng = thread_rng();
+        let max_bytes = self.max_bytes.unwrap_or(usize::MAX);
+        let mut password = SecretString::with_capacity(self.length * 4);
+
+        // Throwing away passwords that miss a required class keeps every valid password equally likely
+        for _ in 0..MAX_ATTEMPTS {
+            password.clear();
+            for _ in 0..self.length {
+                let c = *pool.choose(&mut rng).unwrap();
+                if password.len() + c.len_utf8() > max_bytes {
+                    break;
+                }
+                password.push(c);
+            }
+
+            if self.required.iter().all(|set| password.chars().any(|c| set.iter().any(|class| class.contains(c))))
+                && self.max_consecutive.is_none_or(|max| longest_run(&password) <= max) {
+                return Ok(password);
+            }
+        }
+        Err(ProfileError::Unsatisfiable)
+    }
+
+    fn pool(&self) -> Rc<[char]> {
+        self.pool.get_or_init(|| {
+            let mut pool: Vec<char> = self.classes.iter().flat_map(Class::chars).collect();
+            pool.sort_unstable();
+            pool.dedup();
+            if self.no_lookalikes {
+                pool.retain(|&c| !LOOKALIKES.contains(c));
+            }
+            pool.into()
+        }).clone()
+    }
+
+    /// This profile bent to fit a site's rules, characters from the rules if they give any and a length between their limits.
+    /// Passphrases hardly ever fit rules, so it's always made of characters.
+    pub fn with_rules(&self, rules: &Rules) -> Profile {
+        let mut profile = self.clone();
+        profile.words = None;
+        profile.pool = OnceCell::new();
+        if let Some(max) = rules.max_length {
+            profile.length = profile.length.min(max);
+        }
+        if let Some(min) = rules.min_length {
+            profile.length = profile.length.max(min);
+        }
+        // Passphrase profiles have no characters of their own
+        if profile.classes.is_empty() {
+            profile.classes = vec![Class::Lower, Class::Upper, Class::Digits, Class::Symbols];
+        }
+
+        if !rules.allowed.is_empty() || !rules.required.is_empty() {
+            profile.classes = rules.allowed.clone();
+            profile.required = rules.required.iter().map(|(_, set)| set.clone()).collect();
+            for class in profile.required.concat() {
+                if !profile.classes.contains(&class) {
+                    profile.classes.push(class);
+                }
+            }
+        }
+        profile.max_consecutive = rules.max_consecutive;
+        profile
+    }
+
+    /// Roughly how many bits of randomness a generated password has, ignoring byte limits
+    pub fn entropy(&self) -> f64 {
+        match self.words {
+            Some(words) => {
+                let mut bits = words as f64 * (wordlist().len() as f64).log2();
+                if let Case::Random = self.case {
+                    bits += words as f64;
+                }
+                bits + self.digits as f64 * (10f64.log2() + (words as f64).log2())
+            },
+            None => self.length as f64 * (self.pool().len() as f64).log2(),
+        }
+    }
+
+    fn passphrase(&self, words: usize) -> Result<SecretString, ProfileError> {
+        let wordlist = wordlist();
+        let mut rng = thread_rng();
+        let max_bytes = self.max_bytes.unwrap_or(usize::MAX);
+        let mut passphrase = SecretString::with_capacity(words * (10 + self.separator.len()) + self.digits);
+
+        for _ in 0..MAX_ATTEMPTS {
+            let mut digits = vec![SecretString::default(); words];
+            for _ in 0..self.digits {
+                digits[rng.gen_range(0..words)].push(char::from(b'0' + rng.gen_range(0..10)));
+            }
+
+            passphrase.clear();
+            for (index, word_digits) in digits.iter().enumerate() {
+                if index != 0 {
+                    passphrase.push_str(&self.separator);
+                }
+                let word = wordlist.choose(&mut rng).unwrap();
+                let title = match self.case {
+                    Case::Title => true,
+                    Case::Random => rng.gen(),
+                    _ => false,
+                };
+                for (char_index, c) in word.chars().enumerate() {
+                    match self.case {
+                        Case::Upper => passphrase.push(c.to_ascii_uppercase()),
+                        _ if title && char_index == 0 => passphrase.push(c.to_ascii_uppercase()),
+                        _ => passphrase.push(c),
+                    }
+                }
+                passphrase.push_str(word_digits);
+            }
+
+            if passphrase.len() <= max_bytes {
+                return Ok(passphrase);
+            }
+        }
+        Err(ProfileError::Unsatisfiable)
+    }
+}
+
+fn longest_run(password: &str) -> usize {
+    let mut chars = password.chars().peekable();
+    let mut longest = 0;
+    while let Some(c) = chars.next() {
+        let mut run = 1;
+        while chars.next_if_eq(&c).is_some() {
+            run += 1;
+        }
+        longest = longest.max(run);
+    }
+    longest
+}
+
+impl Rules {
+    pub fn parse(text: &str) -> Result<Rules, ProfileError> {
+        let mut rules = Rules::default();
+
+        for rule in split_outside_sets(text, ';').into_iter().map(str::trim).filter(|rule| !rule.is_empty()) {
+            let bad_rule = || ProfileError::BadRule(rule.to_string());
+            let (name, value) = rule.split_once(':').ok_or_else(bad_rule)?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "required" => rules.required.push((value.to_string(), rule_classes(value).ok_or_else(bad_rule)?)),
+                "allowed" => rules.allowed.extend(rule_classes(value).ok_or_else(bad_rule)?),
+                "max-consecutive" => rules.max_consecutive = Some(value.parse().map_err(|_| bad_rule())?),
+                "minlength" => rules.min_length = Some(value.parse().map_err(|_| bad_rule())?),
+                "maxlength" => rules.max_length = Some(value.parse().map_err(|_| bad_rule())?),
+                _ => return Err(bad_rule()),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// What's wrong with a password, `None` if it fits the rules
+    pub fn check(&self, password: &str) -> Option<String> {
+        let length = password.chars().count();
+        if let Some(min) = self.min_length.filter(|&min| length < min) {
+            return Some(format!("shorter than {} characters", min));
+        }
+        if let Some(max) = self.max_length.filter(|&max| length > max) {
+            return Some(format!("longer than {} characters", max));
+        }
+
+        // Without any character rules, anything goes
+        if !self.allowed.is_empty() || !self.required.is_empty() {
+            let classes: Vec<&Class> = self.allowed.iter().chain(self.required.iter().flat_map(|(_, set)| set)).collect();
+            if let Some(c) = password.chars().find(|&c| !classes.iter().any(|class| class.contains(c))) {
+                return Some(format!("`{}` isn't allowed", c));
+            }
+        }
+
+        if let Some((text, _)) = self.required.iter().find(|(_, set)| !password.chars().any(|c| set.iter().any(|class| class.contains(c)))) {
+            return Some(format!("needs one of `{}`", text));
+        }
+        if let Some(max) = self.max_consecutive.filter(|&max| longest_run(password) > max) {
+            return Some(format!("has a character more than {} times in a row", max));
+        }
+        None
+    }
+}
+
+/// Splits on `separator`, except inside `[...]` sets, which can hold any character - a `]` only ends a set
+/// if it isn't followed by another, so `[abc]]` is `a`, `b`, `c` and `]`
+fn split_outside_sets(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_set = false;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '[' if !in_set => in_set = true,
+            ']' if in_set && chars.peek().map(|&(_, next)| next) != Some(']') => in_set = false,
+            c if c == separator && !in_set => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            },
+            _ => {},
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Comma separated classes of a `required` or `allowed` rule
+fn rule_classes(value: &str) -> Option<Vec<Class>> {
+    let mut classes = Vec::new();
+    for name in split_outside_sets(value, ',').into_iter().map(str::trim) {
+        if let Some(set) = name.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+            classes.push(Class::Custom(set.chars().collect()));
+            continue;
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "upper" => classes.push(Class::Upper),
+            "lower" => classes.push(Class::Lower),
+            "digit" => classes.push(Class::Digits),
+            // Apple counts the space as special too
+            "special" => classes.extend([Class::Symbols, Class::Custom(vec![' '])]),
+            "ascii-printable" => classes.extend([Class::Lower, Class::Upper, Class::Digits, Class::Symbols, Class::Custom(vec![' '])]),
+            "unicode" => classes.push(Class::Unicode),
+            _ => return None,
+        }
+    }
+    Some(classes)
+}
+
+/// Words of the EFF list, split out on first use since the edit view asks for them every frame
+pub fn wordlist() -> &'static [&'static str] {
+    static WORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDS.get_or_init(|| WORDLIST.lines().filter_map(|line| line.split('\t').nth(1)).collect())
+}
+
+/// Built in profiles then ones from PASSRS_PROFILES (`name:spec;name:spec`), default first
+pub fn profiles() -> Result<Vec<Profile>, ProfileError> {
+    let mut profiles = BUILTIN.iter().map(|(name, spec)| Profile::parse(name, spec)).collect::<Result<Vec<_>, _>>()?;
+
+    if let Ok(custom) = env::var("PASSRS_PROFILES") {
+        for entry in custom.split(';').filter(|entry| !entry.is_empty()) {
+            let (name, spec) = entry.split_once(':').ok_or_else(|| ProfileError::BadOption(entry.to_string()))?;
+            let profile = Profile::parse(name, spec)?;
+            // Custom profiles can replace built in ones
+            profiles.retain(|existing| existing.name != name);
+            profiles.push(profile);
+        }
+    }
+
+    if let Ok(default) = env::var("PASSRS_PROFILE") {
+        let index = profiles.iter().position(|profile| profile.name == default).ok_or(ProfileError::Unknown(default))?;
+        let profile = profiles.remove(index);
+        profiles.insert(0, profile);
+    }
+    Ok(profiles)
+}
+
+/// The built in default profile, for when a configured one can't make a password
+pub fn fallback() -> Profile {
+    Profile::parse(BUILTIN[0].0, BUILTIN[0].1).unwrap()
+}
+
+/// A profile by name, or an unnamed one straight from a spec
+pub fn find(profiles: &[Profile], name_or_spec: &str) -> Result<Profile, ProfileError> {
+    match profiles.iter().find(|profile| profile.name == name_or_spec) {
+        Some(profile) => Ok(profile.clone()),
+        None if name_or_spec.contains('=') => Profile::parse("custom", name_or_spec),
+        None => Err(ProfileError::Unknown(name_or_spec.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rules sites actually publish, from Apple's password manager resources
+    const APPLE: &str = "minlength: 8; maxlength: 63; required: lower; required: upper; required: digit; allowed: ascii-printable;";
+    const BANK: &str = "minlength: 8; maxlength: 20; max-consecutive: 3; required: lower; required: upper; required: digit; allowed: [-@#*()+={}/?~;,._];";
+    const DOCS: &str = "required: upper; required: digit; allowed: lower, [-().&@?'#,/\"+]; max-consecutive: 2; minlength: 8";
+
+    #[test]
+    fn rules_check() {
+        let rules = Rules::parse(APPLE).unwrap();
+        assert_eq!(rules.check("Passw0rd"), None);
+        assert_eq!(rules.check("Pass w0rd~"), None);
+        assert_eq!(rules.check("Pa55"), Some("shorter than 8 characters".to_string()));
+        assert_eq!(rules.check(&"Pa55".repeat(16)), Some("longer than 63 characters".to_string()));
+        assert_eq!(rules.check("passw0rd"), Some("needs one of `upper`".to_string()));
+        assert_eq!(rules.check("Passwörd1"), Some("`ö` isn't allowed".to_string()));
+
+        let rules = Rules::parse(BANK).unwrap();
+        assert_eq!(rules.check("Aaaa1b@c"), None);
+        assert_eq!(rules.check("aaaaB1cd"), Some("has a character more than 3 times in a row".to_string()));
+        assert_eq!(rules.check("Abc1234!"), Some("`!` isn't allowed".to_string()));
+    }
+
+    #[test]
+    fn rules_sets() {
+        let rules = Rules::parse(DOCS).unwrap();
+        assert_eq!(rules.check("Abcdef1,"), None);
+        assert_eq!(rules.check("Abcdef1\""), None);
+        assert_eq!(rules.check("Abbbcd1-"), Some("has a character more than 2 times in a row".to_string()));
+        assert_eq!(rules.check("Abcdef1!"), Some("`!` isn't allowed".to_string()));
+
+        // `;` and `,` inside a set don't split it, and `]]` is a `]` in the set
+        let rules = Rules::parse("allowed: lower, [;,]]; minlength: 3").unwrap();
+        assert_eq!(rules.check("a;,]"), None);
+        assert_eq!(rules.check("a;[]"), Some("`[` isn't allowed".to_string()));
+    }
+
+    #[test]
+    fn bad_rules() {
+        for text in ["minlength: eight", "colour: red", "allowed: nonsense", "required"] {
+            assert!(matches!(Rules::parse(text), Err(ProfileError::BadRule(_))), "{}", text);
+        }
+        assert!(Rules::parse("").unwrap().check("anything").is_none());
+    }
+
+    fn passphrases(spec: &str) -> Vec<String> {
+        let profile = Profile::parse("test", spec).unwrap();
+        (0..50).map(|_| profile.generate().unwrap().as_str().to_string()).collect()
+    }
+
+    #[test]
+    fn wordlist_words() {
+        let words = wordlist();
+        assert_eq!(words.len(), 7776);
+        assert_eq!((words[0], words[7775]), ("abacus", "zoom"));
+        assert!(words.iter().all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase() || c == '-')));
+    }
+
+    #[test]
+    fn passphrase_words() {
+        for passphrase in passphrases("words=6,separator=space") {
+            let words: Vec<_> = passphrase.split(' ').collect();
+            assert_eq!(words.len(), 6, "{}", passphrase);
+            assert!(words.iter().all(|word| wordlist().contains(word)), "{}", passphrase);
+        }
+        for passphrase in passphrases("words=4,separator=none") {
+            assert!(!passphrase.contains(' '), "{}", passphrase);
+        }
+    }
+
+    #[test]
+    fn passphrase_case() {
+        for passphrase in passphrases("words=5,separator=.,case=title") {
+            for word in passphrase.split('.') {
+                let mut chars = word.chars();
+                assert!(chars.next().unwrap().is_ascii_uppercase(), "{}", passphrase);
+                assert!(wordlist().contains(&word.to_ascii_lowercase().as_str()), "{}", passphrase);
+            }
+        }
+        for passphrase in passphrases("words=5,separator=.,case=upper") {
+            assert_eq!(passphrase, passphrase.to_ascii_uppercase());
+        }
+        for passphrase in passphrases("words=5,separator=.") {
+            assert_eq!(passphrase, passphrase.to_ascii_lowercase());
+        }
+    }
+
+    #[test]
+    fn passphrase_digits() {
+        for passphrase in passphrases("words=3,separator=_,digits=4") {
+            assert_eq!(passphrase.chars().filter(char::is_ascii_digit).count(), 4, "{}", passphrase);
+            // Digits only ever go on the end of a word
+            for word in passphrase.split('_') {
+                let bare = word.trim_end_matches(|c: char| c.is_ascii_digit());
+                assert!(wordlist().contains(&bare), "{}", passphrase);
+            }
+        }
+    }
+
+    #[test]
+    fn entropy() {
+        let bits = |spec: &str| Profile::parse("test", spec).unwrap().entropy();
+        // log2(7776) is a little under 12.93 bits a word
+        assert!((bits("words=6") - 77.55).abs() < 0.01);
+        assert!((bits("words=6,case=random") - 83.55).abs() < 0.01);
+        assert!((bits("words=4,digits=1") - (4.0 * 7776f64.log2() + 10f64.log2() + 2.0)).abs() < 1e-9);
+        assert!((bits("length=20,chars=lower+upper+digits") - 20.0 * 62f64.log2()).abs() < 1e-9);
+        assert!((bits(BUILTIN[0].1) - 24.0 * 94f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn generated_passwords_follow_rules() {
+        let profiles = [
+            Profile::parse("default", BUILTIN[0].1).unwrap(),
+            Profile::parse("words", BUILTIN[5].1).unwrap(),
+        ];
+        for text in [APPLE, BANK, DOCS] {
+            let rules = Rules::parse(text).unwrap();
+            for profile in &profiles {
+                for _ in 0..20 {
+                    let password = profile.with_rules(&rules).generate().unwrap();
+                    assert_eq!(rules.check(&password), None, "{} with `{}`", password.as_str(), text);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::{ io, path::Path };
+use super::secret::SecretBytes;
+#[cfg(target_os = "linux")]
+use linux_keyutils::{ KeyError, KeyRing, KeyRingIdentifier };
+
+// The data key is kept in the session keyring, so it's gone at logout and only readable by
+// processes in this session. The kernel drops it by itself once the timeout runs out.
+
+/// One entry per data file
+fn description(file: &Path) -> String {
+    format!("passrs:{}", file.display())
+}
+
+#[cfg(target_os = "linux")]
+fn session() -> io::Result<KeyRing> {
+    KeyRing::from_special_id(KeyRingIdentifier::Session, false).map_err(to_io)
+}
+
+#[cfg(target_os = "linux")]
+fn to_io(err: KeyError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// Caches the data key for `ttl` seconds, replacing any key already cached for this file
+#[cfg(target_os = "linux")]
+pub fn store(file: &Path, data_key: &[u8], ttl: usize) -> io::Result<()> {
+    let key = session()?.add_key(&description(file), data_key).map_err(to_io)?;
+    key.set_timeout(ttl).map_err(to_io)
+}
+
+/// The cached data key, if there is one that hasn't expired
+#[cfg(target_os = "linux")]
+pub fn lookup(file: &Path) -> Option<SecretBytes> {
+    let key = session().ok()?.search(&description(file)).ok()?;
+    key.read_to_vec().ok().map(SecretBytes::new)
+}
+
+/// Throws the cached key away, `false` if there wasn't one
+#[cfg(target_os = "linux")]
+pub fn forget(file: &Path) -> io::Result<bool> {
+    match session()?.search(&description(file)) {
+        Ok(key) => key.invalidate().map(|_| true).map_err(to_io),
+        Err(KeyError::KeyDoesNotExist) | Err(KeyError::KeyExpired) | Err(KeyError::KeyRevoked) => Ok(false),
+        Err(err) => Err(to_io(err)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn store(_file: &Path, _data_key: &[u8], _ttl: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "kernel keyring is only on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn lookup(_file: &Path) -> Option<SecretBytes> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn forget(_file: &Path) -> io::Result<bool> {
+    Ok(false)
+}
@@ -1,14 +1,33 @@
-use orion::{ aead::{ open, seal, SecretKey }, errors::UnknownCryptoError as CryptoError };
-use std::{ io::{ Stdout, stdout, Write }, process, fs, env, time, cmp::min, ops::Range };
+use std::{ io::{ self, Stdout, stdout, Write }, process, fs, env, time, ops::Range, path::Path };
 use serde::{ Serialize, Deserialize };
 use crossterm::{ queue, execute, cursor, style, terminal, event };
 use std::process::{ Command, Stdio };
+use error::{ Error, LoadError, DecryptError, ParseError, SaveError, EncryptError };
 
 mod totp;
 mod pass;
 mod ui;
+mod crypt;
+mod vault;
+mod store;
+mod merge;
+mod secret;
+mod error;
+mod agent;
+mod keyring;
+mod askpass;
+mod gen;
+mod stamp;
+mod strength;
+mod audit;
+mod breach;
+mod otpauth;
+mod qr;
+mod migration;
+mod authbackup;
 
 const POLL_TIME: time::Duration = time::Duration::from_millis(100);
+const DEFAULT_LOCK_AFTER: u64 = 300;
 const DEFAULT_TAB: Tab = Tab::Totp;
 const COLOURS: [style::Color; 15] = [
     style::Color::Red, style::Color::DarkRed,
@@ -21,7 +40,7 @@ const COLOURS: [style::Color; 15] = [
     style::Color::DarkGrey, /* style::Color::Black, */  // Imagine needing legible text
 ];
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 struct Passwords {
     pass: Vec<pass::Password>,
     totp: Vec<totp::TotpCode>,
@@ -29,6 +48,72 @@ struct Passwords {
     ui_colour: usize,
 }
 
+/// Settings from the command line and environment that only the GUI uses
+struct GuiOptions {
+    copy_cmd: String,
+    lock_after: u64,
+    history_size: usize,
+}
+
+/// Time since the last key press, shared with dialogs so they lock too
+struct IdleTimer {
+    /// `None` while there's nothing to lock, or locking is turned off
+    lock_after: Option<time::Duration>,
+    last_input: time::Instant,
+}
+
+impl IdleTimer {
+    fn expired(&self) -> bool {
+        self.lock_after.is_some_and(|lock_after| self.last_input.elapsed() >= lock_after)
+    }
+
+    fn with_key<'a>(&'a mut self, master_key: Option<&'a crypt::MasterKey>, keyfile_path: &'a str) -> Lock<'a> {
+        Lock { idle: self, master_key, keyfile_path }
+    }
+}
+
+/// Everything a dialog needs to lock and unlock again without closing, so nothing typed into it is lost
+struct Lock<'a> {
+    idle: &'a mut IdleTimer,
+    master_key: Option<&'a crypt::MasterKey>,
+    keyfile_path: &'a str,
+}
+
+impl Lock<'_> {
+    /// Locks if it's been idle long enough, `true` once it's unlocked again and needs redrawing
+    fn lock_if_idle(&mut self) -> bool {
+        match self.master_key {
+            Some(key) if self.idle.expired() => {
+                locked_ui(key, self.keyfile_path);
+                self.idle.last_input = time::Instant::now();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Waits for the next event, `None` if it locked and unlocked in the meantime
+    fn read_event(&mut self) -> Option<event::Event> {
+        loop {
+            if self.lock_if_idle() {
+                return None;
+            }
+            if let Ok(true) = event::poll(POLL_TIME) {
+                self.idle.last_input = time::Instant::now();
+                return Some(event::read().unwrap());
+            }
+        }
+    }
+}
+
+/// What a session changed - copying only touches last used times, which aren't worth a backup
+#[derive(PartialEq)]
+enum Changes {
+    Nothing,
+    UsedTimes,
+    Edits,
+}
+
 #[derive(PartialEq)]
 enum Tab {
     Password,
@@ -38,10 +123,46 @@ enum Tab {
 enum EditMenuValue<'a> {
     String(&'static str, &'a mut String),
     Int(&'static str, &'a mut usize, Range<usize>),
+    Bool(&'static str, &'a mut bool),
+    /// Text field that can be filled in by the password generator,
+    /// following the `passwordrules` in the String value at the index if there is one
+    Generated(&'static str, &'a mut String, &'a [gen::Profile], Option<usize>),
+}
+
+/// Changes made from the command line instead of the GUI, saved the same way
+enum CliEdit {
+    RestorePassword(String, usize),
+    /// Parsed before unlocking, so a bad URI or image doesn't cost a password prompt
+    AddTotp(Vec<totp::TotpCode>),
+}
+
+/// What `totp qr` and `totp export-all` do with what print mode gives them
+enum UriOutput {
+    QrTerminal,
+    QrFile(String),
+    Backup(authbackup::Format, String),
+}
+
+enum BackupAction {
+    List,
+    Restore(usize),
+}
+
+enum SlotAction {
+    List,
+    Add(String),
+    Label(usize, String),
+    Revoke(usize),
+}
+
+enum ConflictAction {
+    Merge,
+    Overwrite,
+    Abort,
 }
 
 enum MasterPassResult {
-    Password(String),
+    Password(secret::SecretString),
     NoPassword,
     Cancel,
 }
@@ -54,6 +175,7 @@ macro_rules! safe_sub {
 }
 
 fn main() {
+    secret::harden_process();
     let mut stdout = stdout();
 
     let mut filename: Option<String> = {
@@ -68,7 +190,31 @@ fn main() {
         }
     };
 
-    let mut script_print: Option<Tab> = None;
+    let mut script_print: Option<agent::Request> = None;
+    let mut run_agent = false;
+    let mut keyring_ttl: u64 = env::var("PASSRS_KEYRING").ok().and_then(|t| t.parse().ok()).unwrap_or(0);
+    let mut forget_key = false;
+    let mut pass_source: Option<askpass::PassSource> = None;
+    let mut generate: Option<String> = None;
+    let mut agent_ttl: u64 = env::var("PASSRS_AGENT_TTL").ok().and_then(|t| t.parse().ok()).unwrap_or(agent::DEFAULT_TTL);
+    let mut kdf_params: Option<crypt::KdfParams> = None;
+    let mut keyfile_path: Option<String> = env::var("PASSRS_KEYFILE").ok().filter(|k| !k.is_empty());
+    let mut backup_action: Option<BackupAction> = None;
+    let mut slot_action: Option<SlotAction> = None;
+    let mut new_keyfile_path: Option<String> = None;
+    let mut keep_backups: usize = env::var("PASSRS_BACKUPS").ok().and_then(|b| b.parse().ok()).unwrap_or(store::DEFAULT_BACKUPS);
+    let mut lock_after: u64 = env::var("PASSRS_LOCK_AFTER").ok().and_then(|l| l.parse().ok()).unwrap_or(DEFAULT_LOCK_AFTER);
+    let mut history_size: usize = env::var("PASSRS_HISTORY").ok().and_then(|h| h.parse().ok()).unwrap_or(pass::DEFAULT_HISTORY);
+    let mut cli_edit: Option<CliEdit> = None;
+    let mut totp_add = false;
+    let mut totp_uri: Option<String> = None;
+    let mut qr_import: Option<String> = None;
+    let mut qr_export = false;
+    let mut qr_out: Option<String> = None;
+    let mut uri_output: Option<UriOutput> = None;
+    let mut backup_import: Option<String> = None;
+    let mut audit_json = false;
+    let mut audit_breaches: Option<String> = None;
 
     let mut args = env::args().skip(1);
     while let Some(arg) = args.next() {
@@ -77,10 +223,143 @@ fn main() {
                 filename = Some(args.next().expect("Expected a filename"));
             },
             "--totp" | "-t" => {
-                script_print = Some(Tab::Totp);
+                script_print = Some(agent::Request::Totp(None));
+            },
+            "--code" => {
+                script_print = Some(agent::Request::Totp(Some(args.next().expect("Expected a TOTP code name"))));
             },
             "--pass" | "-p" => {
-                script_print = Some(Tab::Password);
+                script_print = Some(agent::Request::Pass(None));
+            },
+            "--get" => {
+                script_print = Some(agent::Request::Pass(Some(args.next().expect("Expected a password name"))));
+            },
+            "--show" => {
+                script_print = Some(agent::Request::Show(args.next().expect("Expected a password name")));
+            },
+            "--get-field" => {
+                let name = args.next().expect("Expected a password name");
+                script_print = Some(agent::Request::Field(name, args.next().expect("Expected a field name")));
+            },
+            "--history" => {
+                script_print = Some(agent::Request::History(args.next().expect("Expected a password name")));
+            },
+            "--due" => {
+                script_print = Some(agent::Request::Due(args.next().and_then(|d| d.parse().ok()).expect("Expected number of days")));
+            },
+            "--restore-password" => {
+                let name = args.next().expect("Expected a password name");
+                cli_edit = Some(CliEdit::RestorePassword(name, args.next().and_then(|i| i.parse().ok()).expect("Expected old password number")));
+            },
+            "--generate" | "-g" => {
+                generate = Some(args.next().expect("Expected a generator profile"));
+            },
+            "--list" | "-l" => {
+                script_print = Some(agent::Request::List);
+            },
+            "agent" => {
+                run_agent = true;
+            },
+            "totp" => {
+                match args.next().as_deref() {
+                    Some("add") => totp_add = true,
+                    Some("import-qr") => qr_import = Some(args.next().expect("Expected an image file")),
+                    Some("import") => backup_import = Some(args.next().expect("Expected an authenticator backup file")),
+                    Some("export-all") => {
+                        let format = args.next().and_then(|f| authbackup::Format::parse(&f)).expect("Expected `aegis`, `aegis-encrypted`, `andotp` or `2fas`");
+                        uri_output = Some(UriOutput::Backup(format, args.next().expect("Expected a filename")));
+                        script_print = Some(agent::Request::Codes);
+                    },
+                    Some("qr") => {
+                        script_print = Some(agent::Request::Uri(args.next().expect("Expected a TOTP code name")));
+                        qr_export = true;
+                    },
+                    Some("export") => script_print = Some(agent::Request::Uri(args.next().expect("Expected a TOTP code name"))),
+                    _ => panic!("Expected `add`, `import-qr`, `import`, `export`, `export-all` or `qr` after `totp`"),
+                }
+            },
+            "--out" => {
+                qr_out = Some(args.next().expect("Expected a PNG or SVG filename"));
+            },
+            "--uri" => {
+                totp_uri = Some(args.next().expect("Expected an otpauth:// URI"));
+            },
+            "audit" => {
+                script_print = Some(agent::Request::Audit(false, None));
+            },
+            "--json" => {
+                audit_json = true;
+            },
+            "--breaches" => {
+                audit_breaches = Some(args.next().expect("Expected a breached password list"));
+            },
+            "--agent-ttl" => {
+                agent_ttl = args.next().and_then(|t| t.parse().ok()).expect("Expected number of seconds");
+            },
+            "--stop-agent" => {
+                script_print = Some(agent::Request::Stop);
+            },
+            "--keyring" => {
+                keyring_ttl = args.next().and_then(|t| t.parse().ok()).expect("Expected number of seconds");
+            },
+            "--forget-key" => {
+                forget_key = true;
+            },
+            "--pinentry" => {
+                pass_source = Some(askpass::PassSource::Pinentry(env::var("PASSRS_PINENTRY").unwrap_or("pinentry".to_string())));
+            },
+            "--pass-cmd" => {
+                pass_source = Some(askpass::PassSource::Command(args.next().expect("Expected a command")));
+            },
+            "--pass-fd" => {
+                pass_source = Some(askpass::PassSource::Fd(args.next().and_then(|f| f.parse().ok()).expect("Expected a file descriptor")));
+            },
+            "--pass-file" => {
+                pass_source = Some(askpass::PassSource::File(args.next().expect("Expected a filename")));
+            },
+            "--keyfile" | "-k" => {
+                keyfile_path = Some(args.next().expect("Expected a keyfile"));
+            },
+            "--kdf-mem" => {
+                let mem_kib = args.next().and_then(|m| m.parse().ok()).expect("Expected memory size in KiB");
+                kdf_params.get_or_insert_with(crypt::KdfParams::default).mem_kib = mem_kib;
+            },
+            "--kdf-iter" => {
+                let iterations = args.next().and_then(|i| i.parse().ok()).expect("Expected number of iterations");
+                kdf_params.get_or_insert_with(crypt::KdfParams::default).iterations = iterations;
+            },
+            "--backups" => {
+                keep_backups = args.next().and_then(|b| b.parse().ok()).expect("Expected number of backups");
+            },
+            "--lock-after" => {
+                lock_after = args.next().and_then(|l| l.parse().ok()).expect("Expected number of seconds");
+            },
+            "--history-size" => {
+                history_size = args.next().and_then(|h| h.parse().ok()).expect("Expected number of old passwords");
+            },
+            "--list-backups" => {
+                backup_action = Some(BackupAction::List);
+            },
+            "--restore-backup" => {
+                let index = args.next().and_then(|i| i.parse().ok()).expect("Expected backup number");
+                backup_action = Some(BackupAction::Restore(index));
+            },
+            "--list-slots" => {
+                slot_action = Some(SlotAction::List);
+            },
+            "--add-slot" => {
+                slot_action = Some(SlotAction::Add(args.next().expect("Expected a slot label")));
+            },
+            "--label-slot" => {
+                let index = args.next().and_then(|i| i.parse().ok()).expect("Expected slot number");
+                slot_action = Some(SlotAction::Label(index, args.next().expect("Expected a slot label")));
+            },
+            "--revoke-slot" => {
+                let index = args.next().and_then(|i| i.parse().ok()).expect("Expected slot number");
+                slot_action = Some(SlotAction::Revoke(index));
+            },
+            "--new-keyfile" => {
+                new_keyfile_path = Some(args.next().expect("Expected a keyfile"));
             },
             "--help" | "-h" => {
                 println!("    passrs ~ Terminal Password Manager & Authenticator");
@@ -89,6 +368,71 @@ fn main() {
                 println!("--file, -f FILE     Specify a (possibly encrypted) file to read data from");
                 println!("--totp, -t          Print all current TOTP codes and their names, useful for scripts");
                 println!("--pass, -p          Print all passwords and their names, useful for scripts");
+                println!("--code NAME         Print the current TOTP code called NAME");
+                println!("totp export NAME    Print the TOTP code called NAME as an `otpauth://` URI, secret included,");
+                println!("                        for moving it to another authenticator");
+                println!("totp add --uri URI  Add a TOTP code from an `otpauth://` URI, keeping its issuer, digits, period and");
+                println!("                        algorithm, `--uri -` reads the URI from stdin to keep the secret out of shell history");
+                println!("totp import-qr IMAGE  Add the TOTP codes from the `otpauth://` QR codes in a PNG or JPEG, like a");
+                println!("                        screenshot of a site's 2FA setup page");
+                println!("                        Both also take Google Authenticator's `otpauth-migration://` exports,");
+                println!("                        adding every code in them at once");
+                println!("totp import FILE    Add the TOTP codes from an Aegis (plain or encrypted), andOTP or 2FAS JSON backup,");
+                println!("                        reporting any whose names are taken");
+                println!("totp export-all FORMAT FILE  Save every TOTP code as a backup for another authenticator, FORMAT is");
+                println!("                        `aegis`, `aegis-encrypted`, `andotp` or `2fas`, only readable by you");
+                println!("totp qr NAME        Draw the TOTP code called NAME as an `otpauth://` QR code, to scan with a phone");
+                println!("--out FILE          Save the QR code from `totp qr` to FILE instead, as SVG if it ends in `.svg`");
+                println!("                        or otherwise PNG, only readable by you");
+                println!("--get NAME          Print the password called NAME");
+                println!("--show NAME         Print the username, URLs, notes and other fields of the password called NAME,");
+                println!("                        except secret fields");
+                println!("--get-field NAME FIELD  Print one field of the password called NAME, one of `password`, `username`,");
+                println!("                        `url`, `notes`, `rules` or a custom field, secret or not");
+                println!("--history NAME      Print the old passwords of the password called NAME, numbered newest first");
+                println!("--due DAYS          Print passwords that expire or are due to be changed within DAYS days,");
+                println!("                        0 for ones already overdue, with when they're due - handy for cron");
+                println!("--list, -l          Print the names of all passwords and TOTP codes");
+                println!("--generate, -g PROFILE  Print a new password from a generator profile (`default`, `alnum`,");
+                println!("                        `readable`, `pin`, `unicode`, `words`, `memorable` or from PASSRS_PROFILES)");
+                println!("                        or a profile spec like");
+                println!("                        `length=20,bytes=64,chars=lower+upper+digits+symbols+greek,require=digits,no-lookalikes`");
+                println!("                        or for a passphrase from the EFF wordlist");
+                println!("                        `words=6,separator=space,case=lower|title|upper|random,digits=1`");
+                println!("                        Print modes ask a running agent when no password or keyfile is given");
+                println!("audit               Print weak, reused and old passwords, and passwords without a TOTP code");
+                println!("--json              Print the audit as JSON");
+                println!("--breaches FILE     Also check passwords against Have I Been Pwned's SHA-1 list, offline - either");
+                println!("                        the one big file ordered by hash, or a directory of range files");
+                println!("agent               Unlock the data file once and answer print modes in the background");
+                println!("--agent-ttl SECS    Stop the agent after SECS seconds, default 3600");
+                println!("--stop-agent        Stop the running agent");
+                println!("--keyring SECS      Cache the unlocked key in the kernel session keyring for SECS seconds,");
+                println!("                        print modes use it instead of asking for a password");
+                println!("--forget-key        Remove the data file's key from the kernel keyring");
+                println!("--keyfile, -k FILE  Use the contents of FILE to unlock the data file, with or without a password");
+                println!("--pinentry          Ask for the password with pinentry instead of the GUI dialog or PASSRS_PASS");
+                println!("--pass-cmd CMD      Use the first line printed by the shell command CMD as the password");
+                println!("--pass-fd FD        Read the password from file descriptor FD");
+                println!("--pass-file FILE    Read the password from the first line of FILE");
+                println!("--kdf-mem KIB       Memory used to derive the key from the password, default 65536");
+                println!("--kdf-iter N        Iterations used to derive the key from the password, default 3");
+                println!("                        KDF options apply next time the data file is saved");
+                println!("--backups N         Keep N backups of the data file when saving, default 5");
+                println!("--lock-after SECS   Lock the GUI after SECS seconds without input, default 300, 0 to never lock");
+                println!("                        Only applies when the data file is encrypted");
+                println!("--history-size N    Keep N old passwords for each password, default 10");
+                println!("--restore-password NAME N  Switch the password called NAME back to old password N from `--history`,");
+                println!("                        the current one joins the history");
+                println!("--list-backups      List backups of the data file, newest first");
+                println!("--restore-backup N  Replace the data file with backup N from `--list-backups`");
+                println!("--list-slots        List the key slots that can unlock the data file");
+                println!("--add-slot LABEL    Add a key slot for the password in PASSRS_NEW_PASS and/or the keyfile");
+                println!("                        given with `--new-keyfile FILE`, unlocking with the current ones");
+                println!("--label-slot N LABEL  Rename key slot N from `--list-slots`");
+                println!("--revoke-slot N     Remove key slot N, its password and keyfile stop working, backups included");
+                println!("                        Slot changes leave the data itself and every other slot untouched -");
+                println!("                        the data key isn't rotated, so anyone who already copied it out keeps it");
                 println!("");
                 println!("--help, -h          Print general help");
                 println!("--help-gui, -H      Print help regarding GUI navigation");
@@ -98,11 +442,23 @@ fn main() {
                 println!("    PASSRS_FILE     Set the file to read data from, overridden by `--file`, `-f`");
                 println!("    PASSRS_PASS     Specify the password (or explicitly no password) for passrs to use,");
                 println!("                        bypassing the GUI password dialog");
+                println!("    PASSRS_PINENTRY Set the pinentry program used by `--pinentry`, defaults to `pinentry`");
+                println!("    PASSRS_KEYFILE  Specify a keyfile to unlock the data file with, overridden by `--keyfile`, `-k`");
+                println!("    PASSRS_NEW_PASS The password for the key slot made by `--add-slot`");
+                println!("    PASSRS_BACKUP_PASS The password of encrypted Aegis vaults for `totp import` and `totp export-all`");
+                println!("    PASSRS_PROFILES Extra generator profiles, as `name:spec;name:spec`");
+                println!("    PASSRS_PROFILE  The generator profile new passwords use, defaults to `default`");
                 println!("    PASSRS_COPY     Specify the shell command to copy a password/token via stdin,");
                 println!("                        defaults to `xclip -selection clipboard`");
+                println!("    PASSRS_BACKUPS  Set the number of backups to keep, overridden by `--backups`");
+                println!("    PASSRS_LOCK_AFTER  Set the idle timeout before locking, overridden by `--lock-after`");
+                println!("    PASSRS_HISTORY  Set the number of old passwords to keep, overridden by `--history-size`");
+                println!("    PASSRS_AGENT_SOCK  Socket the agent listens on, defaults to `$XDG_RUNTIME_DIR/passrs-agent`");
+                println!("    PASSRS_AGENT_TTL   Set how long the agent runs for, overridden by `--agent-ttl`");
+                println!("    PASSRS_KEYRING  Cache the key in the kernel keyring for this many seconds, overridden by `--keyring`");
                 println!("");
                 println!("Return code 0:      Nothing went wrong (hopefully)");
-                println!("Return code 1:      Error loading or decrypting data");
+                println!("Return code 1:      Error loading, decrypting or parsing data");
                 println!("Return code 2:      Error saving or encrypting data");
                 return;
             },
@@ -119,23 +475,45 @@ fn main() {
                 println!("    n               Toggle viewing next TOTP code");
                 println!("    y               Copy the selected item to X clipboard");
                 println!("    e               Edit the selected item");
+                println!("    h               View, copy and restore the selected password's old passwords");
+                println!("    u               Copy the selected TOTP code as an `otpauth://` URI, secret included");
+                println!("    Q               Show the selected TOTP code as a QR code, for scanning with a phone");
+                println!("    i               View when the selected item was made, changed and last copied,");
+                println!("                        and when a password is due to be changed (overdue ones are red)");
                 println!("    o               Create a new item and edit it");
-                println!("    p               Change encryption password for the current data file");
+                println!("    p               Change encryption password and keyfile for the key slot used to unlock");
+                println!("                        the current data file, other key slots keep working");
                 println!("    c               Cycle through theme colours");
+                println!("    l               Lock now, the password (and keyfile) is needed to carry on");
                 println!("    Esc/q           Exit and save, excluding items marked for deletion");
                 println!("");
                 println!("In the edit item view:");
                 println!("    Up/Down         Select the above/below field");
                 println!("    Left/Right/Home/End    Move the cursor in a text field");
                 println!("    Left/Right      Increment/Decrement a number field");
+                println!("    Left/Right/Space    Toggle a yes/no field");
+                println!("    Tab             Generate a new password, Shift+Tab switches generator profile first");
+                println!("    Fill in the blank field at the end of a password to add a field, empty its name to remove it");
                 println!("    Enter           Exit and save current item");
                 println!("    Esc             Exit and cancel adding/editing item");
                 println!("    *               Type in the selected text field");
                 println!("");
+                println!("In the password history view:");
+                println!("    Up/Down/j/k     Select the above/below old password");
+                println!("    y               Copy the selected old password to X clipboard");
+                println!("    r/Enter         Restore the selected old password, the current one joins the history");
+                println!("    Esc/q           Go back without restoring");
+                println!("");
                 println!("In the password dialog:");
-                println!("    Enter           Supply the current password, or if empty, disable encryption");
+                println!("    Enter           Supply the current password, or if empty, disable encryption (unless there's a keyfile)");
                 println!("    Escape          Cancel entering password");
+                println!("                        (not while locked, where unsaved changes are kept until it unlocks)");
                 println!("    *               Type in the password field");
+                println!("");
+                println!("In the dialog shown when the data file was changed by something else while open:");
+                println!("    m               Merge both sets of changes, keeping both versions of items changed on both sides");
+                println!("    o               Overwrite the other changes");
+                println!("    Esc/a           Exit without saving");
                 return;
             },
             a => {
@@ -145,9 +523,121 @@ fn main() {
         }
     }
 
+    if totp_add {
+        let uri = totp_uri.expect("Expected `--uri URI` after `totp add`");
+        // `-` keeps the secret out of shell history and the process list
+        let uri = if uri == "-" {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).expect("Could not read the URI from stdin");
+            secret::SecretString::new(line)
+        } else {
+            secret::SecretString::new(uri)
+        };
+        cli_edit = Some(CliEdit::AddTotp(codes_from_uri(&uri)));
+    }
+
+    if let Some(path) = qr_import {
+        let texts = qr::decode(Path::new(&path)).unwrap_or_else(|err| Error::from(LoadError::Qr(err)).exit());
+        let codes = texts.into_iter().flat_map(|text| codes_from_uri(&secret::SecretString::new(text))).collect();
+        cli_edit = Some(CliEdit::AddTotp(codes));
+    }
+
+    if let Some(path) = backup_import {
+        let password = env::var("PASSRS_BACKUP_PASS").ok().map(secret::SecretString::new);
+        let imported = authbackup::import(Path::new(&path), password.as_deref().map(String::as_str))
+            .unwrap_or_else(|err| Error::from(LoadError::Backup(err)).exit());
+        for name in &imported.skipped {
+            eprintln!("Skipped `{}`, passrs only does TOTP codes", name);
+        }
+        cli_edit = Some(CliEdit::AddTotp(imported.codes));
+    }
+
+    // Found out before unlocking, not after
+    if matches!(uri_output, Some(UriOutput::Backup(authbackup::Format::AegisEncrypted, _))) && env::var_os("PASSRS_BACKUP_PASS").is_none() {
+        Error::from(LoadError::Backup(authbackup::BackupError::PasswordRequired)).exit();
+    }
+
+    if qr_export {
+        uri_output = Some(match qr_out {
+            Some(path) => UriOutput::QrFile(path),
+            None => UriOutput::QrTerminal,
+        });
+    }
+
+    if let Some(agent::Request::Audit(ref mut json, ref mut breaches)) = script_print {
+        *json = audit_json;
+        // The agent has its own working directory
+        *breaches = audit_breaches.map(|path| {
+            let path = fs::canonicalize(&path).unwrap_or_else(|err| Error::from(LoadError::Breaches(err)).exit());
+            path.to_string_lossy().into_owned()
+        });
+    }
+
+    if let Some(Err(err)) = kdf_params.map(|params| params.argon2_params()) {
+        Error::from(EncryptError::KdfParams(err)).exit();
+    }
+
+    if let Some(name) = generate {
+        let profiles = gen::profiles().unwrap_or_else(|err| Error::from(LoadError::Profile(err)).exit());
+        let password = gen::find(&profiles, &name).and_then(|profile| profile.generate())
+            .unwrap_or_else(|err| Error::from(LoadError::Profile(err)).exit());
+        println!("{}", password.as_str());
+        return;
+    }
+
     'main: {
         let filename = filename.unwrap();
 
+        match backup_action {
+            Some(BackupAction::List) => {
+                let backups = store::list_backups(Path::new(&filename))
+                    .unwrap_or_else(|err| Error::from(LoadError::Read(err)).exit());
+                for (index, backup) in (1..).zip(&backups) {
+                    let time = chrono::DateTime::<chrono::Local>::from(backup.time);
+                    println!("{}\t{}\t{}", index, time.format("%Y-%m-%d %H:%M:%S"), backup.path.display());
+                }
+                break 'main;
+            },
+            Some(BackupAction::Restore(index)) => {
+                let backups = store::list_backups(Path::new(&filename)).unwrap_or_default();
+                if let Some(backup) = index.checked_sub(1).and_then(|i| backups.get(i)) {
+                    let _lock = lock_data_file(&filename);
+                    if let Err(err) = store::restore_backup(Path::new(&filename), backup, keep_backups) {
+                        Error::from(SaveError::Restore(err)).exit();
+                    }
+                    eprintln!("Restored {}", backup.path.display());
+                } else {
+                    Error::from(LoadError::NoBackup(index)).exit();
+                }
+                break 'main;
+            },
+            None => {},
+        }
+
+        if forget_key {
+            let file = fs::canonicalize(&filename).unwrap_or_else(|err| Error::from(LoadError::Read(err)).exit());
+            match keyring::forget(&file) {
+                Ok(true) => eprintln!("Forgot cached key"),
+                Ok(false) => eprintln!("No key cached for this data file"),
+                Err(err) => Error::from(LoadError::Keyring(err)).exit(),
+            }
+            break 'main;
+        }
+
+        if let Some(SlotAction::List) = slot_action {
+            let bytes = fs::read(&filename).unwrap_or_else(|err| Error::from(LoadError::Read(err)).exit());
+            match vault::parse(&bytes) {
+                Ok(vault::VaultFile::Slotted { slots, .. }) => {
+                    for (index, slot) in (1..).zip(&slots) {
+                        println!("{}\t{}\t{}\t{} KiB, {} iterations", index, slot.label, slot.kind(), slot.params.mem_kib, slot.params.iterations);
+                    }
+                },
+                Ok(_) => Error::from(LoadError::NoKeySlots).exit(),
+                Err(err) => Error::from(LoadError::Format(err)).exit(),
+            }
+            break 'main;
+        }
+
         let copy_cmd: String = {
             if let Ok(pass_env) = env::var("PASSRS_COPY") {
                 pass_env
@@ -156,89 +646,196 @@ fn main() {
             }
         };
 
-        let mut master_pk: Option<SecretKey> = {
-            if let Ok(pass_env) = env::var("PASSRS_PASS") {
-                if pass_env.len() == 0 {
-                    None
-                } else {
-                    let key = generate_orion_key(&pass_env).unwrap();
-                    Some(key)
+        // Scripts use a key cached in the keyring or go through the agent if there is one,
+        // so the password never has to be in their environment
+        if let Some(ref request) = script_print {
+            if env::var_os("PASSRS_PASS").is_none() && keyfile_path.is_none() && pass_source.is_none() {
+                let file = fs::canonicalize(&filename).unwrap_or_default();
+                let cached_set = match request {
+                    agent::Request::Stop => None,
+                    _ => keyring::lookup(&file).and_then(|data_key| reopen_data(&file, crypt::MasterKey::from_data_key(&data_key).as_ref()).ok()),
+                };
+                if let Some(mut password_set) = cached_set {
+                    print_local(&mut password_set, request, uri_output.as_ref());
+                    break 'main;
                 }
 
-            } else {
-                if script_print == None {
-                    enter_alt_screen(&mut stdout);
-                    let pk = match master_pass_ui() {
-                        MasterPassResult::Password(pass) => Some(generate_orion_key(&pass).unwrap()),
-                        MasterPassResult::NoPassword => None,
-                        MasterPassResult::Cancel => { exit_alt_screen(&mut stdout); break 'main; },
-                    };
-                    exit_alt_screen(&mut stdout);
-
-                    pk
-
-                } else {
-                    eprintln!("Print mode requires a password to be specified with PASSRS_PASS");
-                    process::exit(1);
-                    // break 'main;
+                match agent::query(&agent::socket_path(), &file, request) {
+                    Ok(Ok(output)) => { print_output(&output, uri_output.as_ref()); break 'main; },
+                    Ok(Err(message)) => Error::from(LoadError::Agent(message)).exit(),
+                    Err(_) if matches!(request, agent::Request::Stop) => Error::from(LoadError::Agent("no agent is running".to_string())).exit(),
+                    Err(_) => {},
                 }
             }
+        }
+
+        // Held until passrs exits, print mode and the agent only read so don't need it
+        let _lock = if script_print.is_none() && !run_agent { Some(lock_data_file(&filename)) } else { None };
+        let interactive = script_print.is_none() && slot_action.is_none();
+        // Only the UI makes passwords, a typo in PASSRS_PROFILES shouldn't break scripts that just read
+        let profiles = if interactive && cli_edit.is_none() && !run_agent {
+            gen::profiles().unwrap_or_else(|err| Error::from(LoadError::Profile(err)).exit())
+        } else {
+            Vec::new()
         };
 
-        let mut password_set: Passwords = {
-            if let Ok(bytes) = fs::read(&filename) {
-                let json = {
-                    if let Some(ref master_key) = master_pk {
-                        if let Ok(json) = open(master_key, &bytes) {
-                            json
+        let keyfile = keyfile_path.as_ref().map(|path| {
+            crypt::Keyfile::read(path).unwrap_or_else(|err| Error::from(LoadError::Keyfile(path.clone(), err)).exit())
+        });
+
+        let master_pass: Option<secret::SecretString> = {
+            if let Some(ref source) = pass_source {
+                match askpass::read(source) {
+                    Ok(Some(pass)) if pass.is_empty() => None,
+                    Ok(Some(pass)) => Some(pass),
+                    Ok(None) => break 'main,
+                    Err(err) => Error::from(LoadError::Password(err)).exit(),
+                }
 
-                        } else {
-                            eprintln!("Cannot decrypt data with provided password");
-                            process::exit(1);
-                            // break 'main;
-                        }
+            } else if let Ok(pass_env) = env::var("PASSRS_PASS") {
+                // Otherwise every copy command gets handed the master password too
+                env::remove_var("PASSRS_PASS");
+                let pass_env = secret::SecretString::new(pass_env);
 
-                    } else {
-                        bytes
-                    }
+                if pass_env.is_empty() {
+                    None
+                } else {
+                    Some(pass_env)
+                }
+
+            } else if interactive {
+                enter_alt_screen(&mut stdout);
+                let pass = match master_pass_ui() {
+                    MasterPassResult::Password(pass) => Some(pass),
+                    MasterPassResult::NoPassword => None,
+                    MasterPassResult::Cancel => { exit_alt_screen(&mut stdout); break 'main; },
                 };
+                exit_alt_screen(&mut stdout);
 
-                if let Ok(passwords) = serde_json::from_slice::<Passwords>(&json) {
-                    passwords
+                pass
 
-                } else {
-                    eprintln!("Cannot parse raw JSON, you might require a password:\n{}", String::from_utf8_lossy(&json));
-                    process::exit(1);
-                    // break 'main;
-                }
+            } else if keyfile.is_some() {
+                None
+
+            } else {
+                Error::from(DecryptError::PrintModeNeedsPassword).exit();
+            }
+        };
+        let creds = crypt::Credentials { password: master_pass, keyfile };
+
+        if let Some(action) = slot_action {
+            let new_creds = crypt::Credentials {
+                password: env::var("PASSRS_NEW_PASS").ok().filter(|p| !p.is_empty()).map(secret::SecretString::new),
+                keyfile: new_keyfile_path.map(|path| {
+                    crypt::Keyfile::read(&path).unwrap_or_else(|err| Error::from(LoadError::Keyfile(path.clone(), err)).exit())
+                }),
+            };
+            env::remove_var("PASSRS_NEW_PASS");
+
+            edit_slots(&filename, action, &creds, new_creds, kdf_params.unwrap_or_default(), keep_backups)
+                .unwrap_or_else(|err| err.exit());
+            break 'main;
+        }
+
+        // Key used to open the file, and the (possibly different) one used to save it again
+        let mut master_pk: Option<crypt::MasterKey> = None;
+        let loaded_bytes = read_data_file(&filename).unwrap_or_else(|err| err.exit());
+        let loaded_fingerprint = store::Fingerprint::of(loaded_bytes.as_deref());
+
+        let mut password_set: Passwords = {
+            if let Some(ref bytes) = loaded_bytes {
+                let (passwords, key) = open_data(bytes, &creds).unwrap_or_else(|err| err.exit());
+                master_pk = key;
+                passwords
 
             } else {
                 eprintln!("Cannot read file, making new password set");
                 Passwords { pass: Vec::new(), totp: Vec::new(), ui_colour: 0 }
             }
         };
+        let loaded_set = password_set.clone();
+        cache_key(&filename, master_pk.as_ref(), keyring_ttl);
+
+        if run_agent {
+            // Files from before key slots get re-keyed when saved, there's no key to hold onto yet
+            if master_pk.is_none() && !creds.is_empty() {
+                Error::from(LoadError::NoKeySlots).exit();
+            }
+
+            // Only the key stays in memory, the file is read again for every request so edits show up
+            drop(password_set);
+            let file = fs::canonicalize(&filename).unwrap_or_else(|err| Error::from(LoadError::Read(err)).exit());
+            let socket = agent::socket_path();
+            let listener = agent::listen(&socket).unwrap_or_else(|err| Error::from(LoadError::AgentSocket(err)).exit());
+
+            if let Err(err) = agent::daemonize() {
+                let _ = fs::remove_file(&socket);
+                Error::from(LoadError::AgentSocket(err)).exit();
+            }
+            let _ = agent::serve(listener, &file, time::Duration::from_secs(agent_ttl), |request| {
+                let mut password_set = reopen_data(&file, master_pk.as_ref()).map_err(|err| err.to_string())?;
+                script_output(&mut password_set, request).map_err(|err| err.to_string())
+            });
+            let _ = fs::remove_file(&socket);
+            break 'main;
+        }
 
         match script_print {
-            Some(Tab::Password) => {
-                for pass in &password_set.pass {
-                    println!("{}\t{}", pass.name, pass.password);
-                }
-            },
-            Some(Tab::Totp) => {
-                for totp in &mut password_set.totp {
-                    totp.calculate_codes();
-                    println!("{}\t{}", totp.name, totp.get_code(false));
-                }
+            Some(ref request) => {
+                print_local(&mut password_set, request, uri_output.as_ref());
             },
             None => {
                 // Don't crash trying to load a data file from a modded passrs with more than 15 colours
                 password_set.ui_colour %= COLOURS.len();
 
-                enter_alt_screen(&mut stdout);
-                let anything_changed = main_ui(&mut password_set, &mut master_pk, copy_cmd);
-                exit_alt_screen(&mut stdout);
+                // New files and ones from before key slots need a fresh key for saving,
+                // and changed KDF options only re-key the slot that was unlocked
+                if !creds.is_empty() {
+                    match master_pk {
+                        Some(ref mut key) => {
+                            if let Some(params) = kdf_params.filter(|params| *params != key.slots[key.slot].params) {
+                                change_slot(key, &creds, params);
+                            }
+                        },
+                        None => master_pk = Some(new_master_key(&creds, kdf_params.unwrap_or_default())),
+                    }
+                }
+
+                let changes = match cli_edit {
+                    Some(CliEdit::RestorePassword(ref name, index)) => {
+                        let pass = password_set.pass.iter_mut().find(|pass| pass.name == *name)
+                            .unwrap_or_else(|| Error::from(LoadError::NoItem(name.clone())).exit());
+                        if !index.checked_sub(1).is_some_and(|i| pass.restore(i, history_size)) {
+                            Error::from(LoadError::NoHistory(name.clone(), index)).exit();
+                        }
+                        eprintln!("Restored old password {} of `{}`", index, name);
+                        Changes::Edits
+                    },
+                    Some(CliEdit::AddTotp(ref mut codes)) => {
+                        let mut any_added = false;
+                        for code in codes.drain(..) {
+                            match password_set.totp.iter().find(|totp| totp.name == code.name) {
+                                Some(totp) if totp.same_code(&code) => {
+                                    eprintln!("Already have `{}`, skipped", code.name);
+                                    continue;
+                                },
+                                Some(_) => eprintln!("Already have a different `{}`, kept both", code.name),
+                                None => eprintln!("Added TOTP code `{}`", code.name),
+                            }
+                            password_set.totp.push(code);
+                            any_added = true;
+                        }
+                        if any_added { Changes::Edits } else { Changes::Nothing }
+                    },
+                    None => {
+                        enter_alt_screen(&mut stdout);
+                        let changes = main_ui(&mut password_set, &mut master_pk, kdf_params.unwrap_or_default(), keyfile_path.unwrap_or_default(), &GuiOptions { copy_cmd, lock_after, history_size }, &profiles);
+                        exit_alt_screen(&mut stdout);
+                        changes
+                    },
+                };
 
-                if !anything_changed {
+                if changes == Changes::Nothing {
                     eprintln!("Nothing changed, not saving");
                     process::exit(0);
                 }
@@ -246,45 +843,77 @@ fn main() {
                 password_set.pass.retain(|p| !p.delete);
                 password_set.totp.retain(|t| !t.delete);
 
-                let bytes = {
-                    let json = serde_json::to_string(&password_set).unwrap();
+                let disk_bytes = read_data_file(&filename).unwrap_or_else(|err| err.exit());
+                if store::Fingerprint::of(disk_bytes.as_deref()) != loaded_fingerprint {
+                    // No one to ask when editing from the command line
+                    if cli_edit.is_some() {
+                        Error::from(SaveError::Conflict).exit();
+                    }
+                    enter_alt_screen(&mut stdout);
+                    let action = conflict_ui();
+                    exit_alt_screen(&mut stdout);
 
-                    if let Some(ref master_key) = master_pk {
-                        if let Ok(bytes) = seal(master_key, &json.clone().into_bytes()) {
-                            bytes
-                        } else {
-                            eprintln!("Could not encrypt JSON:\n{}", &json);
-                            process::exit(2);
-                            // break 'main;
-                        }
-                    } else {
-                        json.into_bytes()
+                    match action {
+                        ConflictAction::Merge => {
+                            match disk_bytes.as_deref().map(|bytes| open_data(bytes, &creds)) {
+                                Some(Ok((disk_set, _))) => {
+                                    let mut conflicts = merge::merge(&mut password_set.pass, &loaded_set.pass, &disk_set.pass);
+                                    conflicts.extend(merge::merge(&mut password_set.totp, &loaded_set.totp, &disk_set.totp));
+                                    for name in conflicts {
+                                        eprintln!("Changed both here and on disk, kept both: {}", name);
+                                    }
+                                },
+                                Some(Err(err)) => {
+                                    Error::from(SaveError::Merge(Box::new(err))).exit();
+                                },
+                                None => {
+                                    eprintln!("Data file was deleted, nothing to merge");
+                                },
+                            }
+                        },
+                        ConflictAction::Overwrite => {},
+                        ConflictAction::Abort => {
+                            Error::from(SaveError::Conflict).exit();
+                        },
                     }
+                }
+
+                let bytes = {
+                    let json = secret::SecretBytes::new(serde_json::to_vec(&password_set).unwrap());
+                    vault::write(master_pk.as_ref(), &json).unwrap_or_else(|| Error::from(EncryptError::Seal).exit())
                 };
 
-                if fs::write(&filename, bytes).is_err() {
-                    eprintln!("Could not save file");
-                    process::exit(2);
-                    // break 'main;
+                let keep_backups = if changes == Changes::UsedTimes { 0 } else { keep_backups };
+                if let Err(err) = save_data(&filename, &bytes, master_pk.as_ref(), keep_backups) {
+                    Error::from(SaveError::Write(err)).exit();
                 }
+                // The key might have changed, or encryption been turned off
+                cache_key(&filename, master_pk.as_ref(), keyring_ttl);
             },
         }
     }
 }
 
-fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy_cmd: String) -> bool {
+fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<crypt::MasterKey>, kdf_params: crypt::KdfParams, mut keyfile_path: String, options: &GuiOptions, profiles: &[gen::Profile]) -> Changes {
     let mut stdout = stdout();
 
     use event::KeyCode;
     let mut tab = DEFAULT_TAB;
     let mut show_all = false;
     let mut totp_next = false;
-    let mut totp_last_time: u64 = 0;
     let mut pass_scroll: usize = 0;
     let mut totp_scroll: usize = 0;
     let mut anything_changed = false;
+    let mut used_changed = false;
+    let mut idle = IdleTimer { lock_after: None, last_input: time::Instant::now() };
 
     'ui: loop {
+        // Nothing to check a password against if the file isn't encrypted
+        idle.lock_after = master_pk.as_ref()
+            .filter(|_| options.lock_after != 0)
+            .map(|_| time::Duration::from_secs(options.lock_after));
+        idle.with_key(master_pk.as_ref(), &keyfile_path).lock_if_idle();
+
         let size = terminal::size().unwrap();
         let list_scroll = match tab { Tab::Password => &mut pass_scroll, Tab::Totp => &mut totp_scroll };
         let list_length = match tab { Tab::Password => password_set.pass.len(), Tab::Totp => password_set.totp.len() };
@@ -298,6 +927,7 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                            terminal::Clear(terminal::ClearType::All),
                            cursor::MoveTo(ui::center_offset(size.0, 9), 0),
                            style::Print("Passwords"));
+                    let now = stamp::now();
 
                     for (index, y_pos) in view.zip(1..size.1) {
                         let this_pass = &password_set.pass[index];
@@ -316,12 +946,18 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                             queue!(stdout, style::Print(style::Attribute::CrossedOut));
                         }
 
-                        if index == *list_scroll {
-                            queue!(stdout, style::SetForegroundColor(COLOURS[password_set.ui_colour]));
-                        }
-
+                        let (mark, mark_colour) = ui::strength_mark(this_pass.score());
                         queue!(stdout,
-                               cursor::MoveTo(1, y_pos),
+                               cursor::MoveTo(0, y_pos),
+                               style::SetForegroundColor(mark_colour),
+                               style::Print(mark),
+                               style::SetForegroundColor(if index == *list_scroll {
+                                   COLOURS[password_set.ui_colour]
+                               } else if this_pass.expired(now) {
+                                   style::Color::Red
+                               } else {
+                                   style::Color::Reset
+                               }),
                                style::Print(clip_string(&this_pass.name, safe_sub!(size.0, 1) as usize)));
 
                         if this_pass.name.len() as u16 + 6 <= size.0 && (index == *list_scroll || show_all) {
@@ -352,14 +988,13 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
 
                     let time = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap();
 
-                    {
-                        let totp_now_time = time.as_secs() / 30;
-                        if totp_last_time != totp_now_time {
-                            totp_next = false;
-                            for totp_code in &mut password_set.totp {
-                                totp_code.calculate_codes();
+                    for (index, totp_code) in password_set.totp.iter_mut().enumerate() {
+                        if totp_code.codes_expired(time.as_secs()) {
+                            // The next code of the selected one is now the current code
+                            if index == *list_scroll {
+                                totp_next = false;
                             }
-                            totp_last_time = totp_now_time;
+                            totp_code.calculate_codes();
                         }
                     }
 
@@ -387,7 +1022,8 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                         }
 
                         if index == *list_scroll {
-                            let string_split = (totp_string.len() as f32 * ((time.as_millis() % 30000) as f32 / 30000.0)) as usize + if totp_next { 0 } else { 1 };
+                            let step_millis = this_totp.data.step as u128 * 1000;
+                            let string_split = (totp_string.len() as f32 * ((time.as_millis() % step_millis) as f32 / step_millis as f32)) as usize + if totp_next { 0 } else { 1 };
                             let string_parts = (&totp_string[..string_split].to_string(), &totp_string[string_split..].to_string());
                             let colours = if totp_next { (style::Color::Black, COLOURS[password_set.ui_colour]) } else { (COLOURS[password_set.ui_colour], style::Color::Black) };
                             queue!(stdout,
@@ -418,9 +1054,24 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
         if let Ok(true) = event::poll(POLL_TIME) {
             let ev = event::read().unwrap();
             let keyev = ui::input_key(&ev);
+            idle.last_input = time::Instant::now();
 
             match keyev {
-                KeyCode::Esc | KeyCode::Char('q') => break 'ui anything_changed,
+                KeyCode::Esc | KeyCode::Char('q') => {
+                    break 'ui if anything_changed {
+                        Changes::Edits
+                    } else if used_changed {
+                        Changes::UsedTimes
+                    } else {
+                        Changes::Nothing
+                    };
+                },
+                KeyCode::Char('l') => {
+                    if let Some(ref key) = master_pk {
+                        locked_ui(key, &keyfile_path);
+                        idle.last_input = time::Instant::now();
+                    }
+                },
                 KeyCode::Tab => {
                     tab = match tab { Tab::Password => Tab::Totp, Tab::Totp => Tab::Password };
                 },
@@ -455,16 +1106,16 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                     *list_scroll = list_length - 1;
                 },
                 KeyCode::Char('p') => {
-                    match master_pass_ui() {
-                        MasterPassResult::Password(pass) => {
-                            *master_pk = Some(generate_orion_key(&pass).unwrap());
-                            anything_changed = true;
-                        },
-                        MasterPassResult::NoPassword => {
-                            *master_pk = None;
-                            anything_changed = true;
-                        },
-                        MasterPassResult::Cancel => {},
+                    let changed = change_credentials_ui(&keyfile_path, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path));
+                    if let Some((creds, new_keyfile_path)) = changed {
+                        keyfile_path = new_keyfile_path;
+                        // Only the slot this was unlocked with changes, other people's passwords still work
+                        match master_pk {
+                            _ if creds.is_empty() => *master_pk = None,
+                            Some(ref mut key) => change_slot(key, &creds, kdf_params),
+                            None => *master_pk = Some(new_master_key(&creds, kdf_params)),
+                        }
+                        anything_changed = true;
                     }
                 },
                 KeyCode::Char('v') => {
@@ -481,12 +1132,64 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                     if list_length != 0 {
                         match tab {
                             Tab::Password => {
-                                clipboard(&password_set.pass[pass_scroll].password, &copy_cmd);
+                                clipboard(&password_set.pass[pass_scroll].password, &options.copy_cmd);
+                                password_set.pass[pass_scroll].used = stamp::now();
                             },
                             Tab::Totp => {
-                                clipboard(&password_set.totp[totp_scroll].get_code(totp_next).to_string(), &copy_cmd);
+                                clipboard(&password_set.totp[totp_scroll].get_code(totp_next).to_string(), &options.copy_cmd);
+                                password_set.totp[totp_scroll].used = stamp::now();
                             },
                         }
+                        // Saved for the last used time, without using up a backup
+                        used_changed = true;
+                    }
+                },
+                KeyCode::Char('i') => {
+                    if list_length != 0 {
+                        let ui_colour = COLOURS[password_set.ui_colour];
+                        match tab {
+                            Tab::Password => {
+                                let this_pass = &password_set.pass[pass_scroll];
+                                let due = match this_pass.due() {
+                                    Some(due) if due <= stamp::now() => format!("{}, overdue", stamp::format(due)),
+                                    Some(due) => stamp::format(due),
+                                    None => "never".to_string(),
+                                };
+                                details_ui(&this_pass.name, &[
+                                    ("Created", stamp::format(this_pass.created)),
+                                    ("Modified", stamp::format(this_pass.modified)),
+                                    ("Password changed", stamp::format(this_pass.changed)),
+                                    ("Last copied", stamp::format(this_pass.used)),
+                                    ("Change by", due),
+                                ], ui_colour, &mut idle.with_key(master_pk.as_ref(), &keyfile_path));
+                            },
+                            Tab::Totp => {
+                                let this_totp = &password_set.totp[totp_scroll];
+                                details_ui(&this_totp.name, &[
+                                    ("Created", stamp::format(this_totp.created)),
+                                    ("Modified", stamp::format(this_totp.modified)),
+                                    ("Last copied", stamp::format(this_totp.used)),
+                                ], ui_colour, &mut idle.with_key(master_pk.as_ref(), &keyfile_path));
+                            },
+                        }
+                    }
+                },
+                KeyCode::Char('h') => {
+                    if let (Tab::Password, Some(this_pass)) = (&tab, password_set.pass.get_mut(pass_scroll)) {
+                        if let Some(index) = history_ui(this_pass, &options.copy_cmd, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path)) {
+                            this_pass.restore(index, options.history_size);
+                            anything_changed = true;
+                        }
+                    }
+                },
+                KeyCode::Char('u') => {
+                    if let (Tab::Totp, Some(this_totp)) = (&tab, password_set.totp.get(totp_scroll)) {
+                        clipboard(&otpauth::to_uri(this_totp), &options.copy_cmd);
+                    }
+                },
+                KeyCode::Char('Q') => {
+                    if let (Tab::Totp, Some(this_totp)) = (&tab, password_set.totp.get(totp_scroll)) {
+                        qr_ui(&this_totp.name, &otpauth::to_uri(this_totp), COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path));
                     }
                 },
                 KeyCode::Char('d') => {
@@ -509,10 +1212,8 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                                 let this_pass: &mut pass::Password = &mut password_set.pass[pass_scroll];
                                 let mut temp_pass: pass::Password = this_pass.clone();
 
-                                if edit_values_ui("Edit Password", &mut [
-                                    EditMenuValue::String("Name", &mut temp_pass.name),
-                                    EditMenuValue::String("Password", &mut temp_pass.password),
-                                ], COLOURS[password_set.ui_colour]) {
+                                if edit_password_ui("Edit Password", &mut temp_pass, profiles, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path)) {
+                                    temp_pass.edited(this_pass, options.history_size);
                                     *this_pass = temp_pass;
                                     anything_changed = true;
                                 }
@@ -522,15 +1223,9 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                             if password_set.totp.len() != 0 {
                                 let this_totp: &mut totp::TotpCode = &mut password_set.totp[totp_scroll];
                                 let mut temp_totp: totp::TotpCode = this_totp.clone();
-                                let mut temp_secret = temp_totp.get_secret_string();
-
-                                if edit_values_ui("Edit TOTP", &mut [
-                                    EditMenuValue::String("Name", &mut temp_totp.name),
-                                    EditMenuValue::Int("Digits", &mut temp_totp.data.digits, 4..8),
-                                    EditMenuValue::String("Secret", &mut temp_secret),
-                                ], COLOURS[password_set.ui_colour]) {
-                                    temp_totp.set_secret_string(temp_secret);
-                                    temp_totp.calculate_codes();
+
+                                if edit_totp_ui(&mut temp_totp, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path)) {
+                                    temp_totp.modified = stamp::now();
                                     *this_totp = temp_totp;
                                     anything_changed = true;
                                 }
@@ -541,12 +1236,13 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                 KeyCode::Char('o') => {
                     match tab {
                         Tab::Password => {
-                            let mut temp_pass = pass::Password::new();
+                            // Rather than start with an empty password when the profile can't make one
+                            let (mut temp_pass, title) = match pass::Password::new(&profiles[0]) {
+                                Ok(temp_pass) => (temp_pass, "Edit Password".to_string()),
+                                Err(err) => (pass::Password::new(&gen::fallback()).unwrap(), format!("Edit Password (`{}` profile: {}, used `default`)", profiles[0].name, err)),
+                            };
 
-                            if edit_values_ui("Edit Password", &mut [
-                                EditMenuValue::String("Name", &mut temp_pass.name),
-                                EditMenuValue::String("Password", &mut temp_pass.password),
-                            ], COLOURS[password_set.ui_colour]) {
+                            if edit_password_ui(&title, &mut temp_pass, profiles, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path)) {
                                 if pass_scroll + 1 >= password_set.pass.len() {
                                     password_set.pass.push(temp_pass);
                                 } else {
@@ -562,15 +1258,8 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
                         },
                         Tab::Totp => {
                             let mut temp_totp = totp::TotpCode::new();
-                            let mut temp_secret = temp_totp.get_secret_string();
-
-                            if edit_values_ui("Edit TOTP", &mut [
-                                EditMenuValue::String("Name", &mut temp_totp.name),
-                                EditMenuValue::Int("Digits", &mut temp_totp.data.digits, 4..8),
-                                EditMenuValue::String("Secret", &mut temp_secret),
-                            ], COLOURS[password_set.ui_colour]) {
-                                temp_totp.set_secret_string(temp_secret);
-                                temp_totp.calculate_codes();
+
+                            if edit_totp_ui(&mut temp_totp, COLOURS[password_set.ui_colour], &mut idle.with_key(master_pk.as_ref(), &keyfile_path)) {
                                 if totp_scroll + 1 >= password_set.totp.len() {
                                     password_set.totp.push(temp_totp);
                                 } else {
@@ -595,7 +1284,7 @@ fn main_ui(password_set: &mut Passwords, master_pk: &mut Option<SecretKey>, copy
 fn master_pass_ui() -> MasterPassResult {
     let mut stdout = stdout();
 
-    let mut pass = String::new();
+    let mut pass = secret::SecretString::with_capacity(256);
 
     let master_pass = 'ui: loop {
         let size = terminal::size().unwrap();
@@ -613,11 +1302,6 @@ fn master_pass_ui() -> MasterPassResult {
 
         let ev = event::read().unwrap();
         let ev_key = &ui::input_key(&ev);
-        if pass.len() >= 32 {
-            if let event::KeyCode::Char(_) = ev_key {
-                continue;
-            }
-        }
 
         let mut index = pass.char_indices().count();
         match ui::input_string(&mut pass, &mut index, ev_key) {
@@ -638,19 +1322,302 @@ fn master_pass_ui() -> MasterPassResult {
     return master_pass;
 }
 
-fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::Color) -> bool {
+/// Blanks the screen until credentials that open one of the key slots are entered again
+fn locked_ui(master_key: &crypt::MasterKey, keyfile_path: &str) {
+    loop {
+        let password = match master_pass_ui() {
+            MasterPassResult::Password(pass) => Some(pass),
+            MasterPassResult::NoPassword => None,
+            // Escape doesn't get past the lock, there's nothing to go back to
+            MasterPassResult::Cancel => continue,
+        };
+        // Read again each time, so pulling out the drive with the keyfile also keeps it locked
+        let keyfile = if keyfile_path.is_empty() { None } else { crypt::Keyfile::read(keyfile_path).ok() };
+
+        if master_key.check(&crypt::Credentials { password, keyfile }) {
+            return;
+        }
+    }
+}
+
+/// New credentials, and the keyfile path to remember for next time
+fn change_credentials_ui(keyfile_path: &str, ui_colour: style::Color, lock: &mut Lock) -> Option<(crypt::Credentials, String)> {
+    let password = match master_pass_ui() {
+        MasterPassResult::Password(pass) => Some(pass),
+        MasterPassResult::NoPassword => None,
+        MasterPassResult::Cancel => return None,
+    };
+
+    let mut title = "Keyfile (empty for none)";
+    loop {
+        let mut path = keyfile_path.to_string();
+        if !edit_values_ui(title, &mut [EditMenuValue::String("Keyfile path", &mut path)], ui_colour, lock) {
+            return None;
+        }
+
+        if path.is_empty() {
+            return Some((crypt::Credentials { password, keyfile: None }, path));
+        }
+
+        if let Ok(keyfile) = crypt::Keyfile::read(&path) {
+            return Some((crypt::Credentials { password, keyfile: Some(keyfile) }, path));
+        }
+        title = "Cannot read keyfile, try again";
+    }
+}
+
+fn conflict_ui() -> ConflictAction {
+    let mut stdout = stdout();
+
+    loop {
+        let size = terminal::size().unwrap();
+        queue!(stdout,
+               terminal::Clear(terminal::ClearType::All),
+               cursor::MoveTo(ui::center_offset(size.0, 39), ui::center_offset(size.1, 0) - 1),
+               style::Print("Data file was changed by something else"),
+               cursor::MoveTo(ui::center_offset(size.0, 36), ui::center_offset(size.1, 0) + 1),
+               style::Print("m: Merge   o: Overwrite   Esc: Abort"));
+
+        stdout.flush();
+
+        match ui::input_key(&event::read().unwrap()) {
+            event::KeyCode::Char('m') => return ConflictAction::Merge,
+            event::KeyCode::Char('o') => return ConflictAction::Overwrite,
+            event::KeyCode::Esc | event::KeyCode::Char('a') => return ConflictAction::Abort,
+            _ => {},
+        }
+    }
+}
+
+/// Read-only facts about an item, until any key is pressed
+fn details_ui(title: &str, details: &[(&str, String)], ui_colour: style::Color, lock: &mut Lock) {
+    let mut stdout = stdout();
+    let label_width = details.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+
+    loop {
+        let size = terminal::size().unwrap();
+        queue!(stdout,
+               terminal::Clear(terminal::ClearType::All),
+               cursor::MoveTo(ui::center_offset(size.0, title.chars().count() as u16), 0),
+               style::Print(title));
+        for ((label, value), y_pos) in details.iter().zip(2..size.1) {
+            queue!(stdout,
+                   cursor::MoveTo(1, y_pos),
+                   style::SetForegroundColor(ui_colour),
+                   style::Print(format!("{:width$}  ", label, width = label_width)),
+                   style::ResetColor,
+                   style::Print(value));
+        }
+        stdout.flush();
+
+        // Resizing and locking aren't key presses, they only need it drawn again
+        match lock.read_event().map(|ev| ui::input_key(&ev)) {
+            None | Some(event::KeyCode::Null) => {},
+            Some(_) => return,
+        }
+    }
+}
+
+fn qr_ui(title: &str, text: &str, ui_colour: style::Color, lock: &mut Lock) {
+    let mut stdout = stdout();
+    let matrix = qr::encode(text);
+
+    loop {
+        let size = terminal::size().unwrap();
+        queue!(stdout,
+               terminal::Clear(terminal::ClearType::All),
+               cursor::MoveTo(ui::center_offset(size.0, title.chars().count() as u16), 0),
+               style::Print(title));
+        match &matrix {
+            Ok(matrix) => {
+                let lines = matrix.half_blocks();
+                let width = matrix.size(qr::TERMINAL_QUIET) as u16;
+                if width > size.0 || lines.len() as u16 + 2 > size.1 {
+                    let message = format!("Make the terminal at least {}x{} to fit the QR code", width, lines.len() + 2);
+                    queue!(stdout,
+                           cursor::MoveTo(ui::center_offset(size.0, message.chars().count() as u16), 2),
+                           style::SetForegroundColor(ui_colour),
+                           style::Print(message),
+                           style::ResetColor);
+                } else {
+                    for (line, y_pos) in lines.iter().zip(2..) {
+                        queue!(stdout,
+                               cursor::MoveTo(ui::center_offset(size.0, width), y_pos),
+                               style::SetForegroundColor(style::Color::Black),
+                               style::SetBackgroundColor(style::Color::White),
+                               style::Print(line.as_str()),
+                               style::ResetColor);
+                    }
+                }
+            },
+            Err(err) => {
+                queue!(stdout,
+                       cursor::MoveTo(1, 2),
+                       style::SetForegroundColor(ui_colour),
+                       style::Print(err),
+                       style::ResetColor);
+            },
+        }
+        stdout.flush();
+
+        // Resizing and locking aren't key presses, they only need it drawn again
+        match lock.read_event().map(|ev| ui::input_key(&ev)) {
+            None | Some(event::KeyCode::Null) => {},
+            Some(_) => return,
+        }
+    }
+}
+
+/// Old passwords of one password, `Some` with the index of one to restore
+fn history_ui(pass: &pass::Password, copy_cmd: &String, ui_colour: style::Color, lock: &mut Lock) -> Option<usize> {
+    use event::KeyCode;
+
+    let mut stdout = stdout();
+    let title = format!("History of {}", pass.name);
+    let mut selected: usize = 0;
+
+    loop {
+        let size = terminal::size().unwrap();
+        queue!(stdout,
+               terminal::Clear(terminal::ClearType::All),
+               cursor::MoveTo(ui::center_offset(size.0, title.chars().count() as u16), 0),
+               style::Print(&title));
+
+        if pass.history.is_empty() {
+            queue!(stdout,
+                   cursor::MoveTo(1, 1),
+                   style::Print("No old passwords yet"));
+        }
+
+        let view = ui::visible_scrolled(safe_sub!(size.1 as usize, 1), pass.history.len(), selected);
+        for (index, y_pos) in view.zip(1..size.1) {
+            let old = &pass.history[index];
+            let when = format!("{:>3}  {}  ", index + 1, old.replaced_time());
+
+            if index == selected {
+                queue!(stdout, style::SetForegroundColor(ui_colour));
+            }
+            queue!(stdout,
+                   cursor::MoveTo(1, y_pos),
+                   style::Print(&when));
+
+            // Only the selected one is shown, like the password list
+            if index == selected {
+                for c in old.password.chars().take(safe_sub!(size.0 as usize, when.len() + 2)) {
+                    queue!(stdout, style::Print(c));
+                }
+            }
+            queue!(stdout, style::ResetColor);
+        }
+
+        stdout.flush();
+
+        let Some(ev) = lock.read_event() else {
+            continue;
+        };
+        match ui::input_key(&ev) {
+            KeyCode::Esc | KeyCode::Char('q') => return None,
+            KeyCode::Up | KeyCode::Char('k') => {
+                selected = selected.saturating_sub(1);
+            },
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < pass.history.len() => {
+                selected += 1;
+            },
+            KeyCode::Char('y') if selected < pass.history.len() => {
+                clipboard(&pass.history[selected].password, copy_cmd);
+            },
+            KeyCode::Char('r') | KeyCode::Enter if selected < pass.history.len() => return Some(selected),
+            _ => {},
+        }
+    }
+}
+
+fn edit_password_ui(title: &str, pass: &mut pass::Password, profiles: &[gen::Profile], ui_colour: style::Color, lock: &mut Lock) -> bool {
+    let mut urls = pass.urls.join(" ");
+    let mut notes = pass::escape_lines(&pass.notes);
+    let mut expiry = pass.expiry_text();
+    // Filling in the blank field on the end adds it, emptying a field's name removes it
+    let mut fields = pass.fields.clone();
+    fields.push(pass::Field::default());
+
+    let mut values = vec![
+        EditMenuValue::String("Name", &mut pass.name),
+        EditMenuValue::Generated("Password", &mut pass.password, profiles, Some(2)),
+        EditMenuValue::String("Password rules, like `minlength: 12; required: digit; allowed: lower, upper`", &mut pass.rules),
+        EditMenuValue::String("Expires, on a date like 2027-01-31 and/or every N days like 90d", &mut expiry),
+        EditMenuValue::String("Username", &mut pass.username),
+        EditMenuValue::String("URLs, separated by spaces", &mut urls),
+        EditMenuValue::String("Notes, \\n for a new line", &mut notes),
+    ];
+    for field in &mut fields {
+        values.push(EditMenuValue::String("Field name", &mut field.name));
+        values.push(EditMenuValue::String("Field value", &mut field.value));
+        values.push(EditMenuValue::Bool("Field is secret", &mut field.secret));
+    }
+
+    if !edit_values_ui(title, &mut values, ui_colour, lock) {
+        return false;
+    }
+
+    pass.urls = urls.split_whitespace().map(str::to_string).collect();
+    pass.notes = pass::unescape_lines(&notes);
+    // Anything it can't make sense of leaves the expiry as it was
+    pass.set_expiry_text(&expiry);
+    fields.retain(|field| !field.name.is_empty());
+    pass.fields = fields;
+    true
+}
+
+fn edit_totp_ui(totp: &mut totp::TotpCode, ui_colour: style::Color, lock: &mut Lock) -> bool {
+    let mut secret = totp.get_secret_string();
+    let mut uri = secret::SecretString::new(String::new());
+
+    if !edit_values_ui("Edit TOTP", &mut [
+        EditMenuValue::String("Name", &mut totp.name),
+        EditMenuValue::Int("Digits", &mut totp.data.digits, 4..8),
+        EditMenuValue::String("Secret", &mut secret),
+        EditMenuValue::String("otpauth:// URI, pasting one in replaces everything above", &mut uri),
+    ], ui_colour, lock) {
+        return false;
+    }
+
+    totp.set_secret_string(&secret);
+    // Leaving it empty, or anything it can't make sense of, keeps the values above
+    if let Ok(code) = otpauth::parse(&uri) {
+        totp.name = code.name;
+        totp.data.algorithm = code.data.algorithm;
+        totp.data.digits = code.data.digits;
+        totp.data.step = code.data.step;
+        totp.set_secret_bytes(code.data.secret.clone());
+    }
+    totp.calculate_codes();
+    true
+}
+
+/// Rules in the String value at `index`, `None` if there isn't one or it's empty
+fn rules_at(values: &[EditMenuValue], index: Option<usize>) -> Option<Result<gen::Rules, gen::ProfileError>> {
+    match values.get(index?) {
+        Some(EditMenuValue::String(_, rules)) if !rules.trim().is_empty() => Some(gen::Rules::parse(rules)),
+        _ => None,
+    }
+}
+
+fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::Color, lock: &mut Lock) -> bool {
     use event::KeyCode;
 
     let mut stdout = stdout();
     let mut selected: usize = 0;
     let mut string_index: usize = {
-        if let EditMenuValue::String(_, string_val) = &values[selected] {
+        if let EditMenuValue::String(_, string_val) | EditMenuValue::Generated(_, string_val, _, _) = &values[selected] {
             string_val.char_indices().count()
         } else {
             0
         }
     }
 ;
+    let mut profile_index: usize = 0;
+    // Only worked out again once the generated value changes
+    let mut strength_cache: Option<strength::Strength> = None;
 
     'ui: loop {
         let size = terminal::size().unwrap();
@@ -660,25 +1627,64 @@ fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::C
                cursor::MoveTo(ui::center_offset(size.0, title.len() as u16), 0),
                style::Print(title));
 
-        for value_index in 0..values.len() {
+        // Scroll once there are more values than fit, three lines each
+        let view = ui::visible_scrolled(safe_sub!(size.1 as usize, 2) / 3, values.len(), selected);
+        for (value_index, y_pos) in view.zip((2..).step_by(3)) {
             if selected == value_index {
                 queue!(stdout, style::SetForegroundColor(ui_colour));
             }
 
             queue!(stdout,
-                   cursor::MoveTo(1, 2 + value_index as u16 * 3));
+                   cursor::MoveTo(1, y_pos));
             match &values[value_index] {
                 EditMenuValue::String(label, string_value) => {
                     queue!(stdout, style::Print(label));
-                    ui::print_typing((5, size.0), 3 + value_index as u16 * 3, string_value,
+                    ui::print_typing((5, size.0), y_pos + 1, string_value,
                                      if selected == value_index { Some(string_index) } else { None });
                 },
                 EditMenuValue::Int(label, int_value, _) => {
                     queue!(stdout,
                            style::Print(label),
-                           cursor::MoveTo(5, 3 + value_index as u16 * 3),
+                           cursor::MoveTo(5, y_pos + 1),
                            style::Print(int_value));
                 },
+                EditMenuValue::Generated(label, string_value, profiles, rules_index) => {
+                    let rules = rules_at(values, *rules_index);
+                    // Borrowed rather than cloned when there are no rules, so its character pool stays cached
+                    let with_rules = match &rules {
+                        Some(Ok(rules)) => Some(profiles[profile_index].with_rules(rules)),
+                        _ => None,
+                    };
+                    let profile = with_rules.as_ref().unwrap_or(&profiles[profile_index]);
+                    let strength = strength_cache.get_or_insert_with(|| strength::estimate(string_value));
+                    let (mark, mark_colour) = ui::strength_mark(strength.score);
+                    queue!(stdout,
+                           style::Print(format!("{}, {} ", label, strength.label())),
+                           style::SetForegroundColor(mark_colour),
+                           style::Print(mark),
+                           style::SetForegroundColor(if selected == value_index { ui_colour } else { style::Color::Reset }),
+                           style::Print(format!(" (Tab: new `{}` password, ~{:.0} bits)", profile.name, profile.entropy())));
+
+                    let problem = match &rules {
+                        Some(Ok(rules)) => rules.check(string_value).map(|problem| format!("breaks the rules, {}", problem)),
+                        Some(Err(err)) => Some(err.to_string()),
+                        None => None,
+                    };
+                    if let Some(problem) = problem {
+                        queue!(stdout,
+                               style::SetForegroundColor(style::Color::Red),
+                               style::Print(format!(" {}", problem)),
+                               style::SetForegroundColor(if selected == value_index { ui_colour } else { style::Color::Reset }));
+                    }
+                    ui::print_typing((5, size.0), y_pos + 1, string_value,
+                                     if selected == value_index { Some(string_index) } else { None });
+                },
+                EditMenuValue::Bool(label, bool_value) => {
+                    queue!(stdout,
+                           style::Print(label),
+                           cursor::MoveTo(5, y_pos + 1),
+                           style::Print(if **bool_value { "yes" } else { "no" }));
+                },
             }
 
             queue!(stdout, style::ResetColor);
@@ -686,9 +1692,15 @@ fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::C
 
         stdout.flush();
 
+        // Locks in place, and everything typed so far is still there once it's unlocked
+        if lock.lock_if_idle() {
+            continue 'ui;
+        }
+
         if let Ok(true) = event::poll(POLL_TIME) {
             let ev = event::read().unwrap();
             let keyev = ui::input_key(&ev);
+            lock.idle.last_input = time::Instant::now();
 
             match keyev {
                 KeyCode::Esc => {
@@ -703,21 +1715,44 @@ fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::C
                     } else {
                         selected = selected - 1;
                     }
-                    if let EditMenuValue::String(_, string_val) = &values[selected] {
+                    if let EditMenuValue::String(_, string_val) | EditMenuValue::Generated(_, string_val, _, _) = &values[selected] {
                         string_index = string_val.char_indices().count();
                     }
                 },
                 KeyCode::Down => {
                     selected = (selected + 1) % values.len();
-                    if let EditMenuValue::String(_, string_val) = &values[selected] {
+                    if let EditMenuValue::String(_, string_val) | EditMenuValue::Generated(_, string_val, _, _) = &values[selected] {
                         string_index = string_val.char_indices().count();
                     }
                 },
                 _ => {
+                    let rules = match &values[selected] {
+                        EditMenuValue::Generated(_, _, _, rules_index) => rules_at(values, *rules_index).and_then(Result::ok),
+                        _ => None,
+                    };
                     match &mut values[selected] {
                         EditMenuValue::String(_, string_val) => {
                             ui::input_string(*string_val, &mut string_index, &keyev);
                         },
+                        EditMenuValue::Generated(_, string_val, profiles, _) => {
+                            strength_cache = None;
+                            if let KeyCode::Tab | KeyCode::BackTab = keyev {
+                                if keyev == KeyCode::BackTab {
+                                    profile_index = (profile_index + 1) % profiles.len();
+                                }
+                                let profile = match &rules {
+                                    Some(rules) => profiles[profile_index].with_rules(rules),
+                                    None => profiles[profile_index].clone(),
+                                };
+                                if let Ok(password) = profile.generate() {
+                                    string_val.clear();
+                                    string_val.push_str(&password);
+                                    string_index = string_val.char_indices().count();
+                                }
+                            } else {
+                                ui::input_string(*string_val, &mut string_index, &keyev);
+                            }
+                        },
                         EditMenuValue::Int(_, int_val, range) => {
                             match keyev {
                                 KeyCode::Left => {
@@ -733,6 +1768,11 @@ fn edit_values_ui(title: &str, values: &mut [EditMenuValue], ui_colour: style::C
                                 _ => {},
                             }
                         },
+                        EditMenuValue::Bool(_, bool_val) => {
+                            if let KeyCode::Left | KeyCode::Right | KeyCode::Char(' ') = keyev {
+                                **bool_val = !**bool_val;
+                            }
+                        },
                     }
                 }
             }
@@ -796,28 +1836,356 @@ fn exit_alt_screen(stdout: &mut Stdout) {
     stdout.flush();
 }
 
-#[inline]
-fn generate_orion_key(key: &str) -> Result<SecretKey, CryptoError> {
-    // Pad with spaces... cring
-    /*
-    let padded = format!("{:32}", key);
-    let padded_bytes = padded.as_bytes();
-    */
+/// `None` if there's no data file yet
+fn read_data_file(filename: &str) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(filename) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(LoadError::Read(err).into()),
+    }
+}
+
+fn open_data(bytes: &[u8], creds: &crypt::Credentials) -> Result<(Passwords, Option<crypt::MasterKey>), Error> {
+    let mut master_pk = None;
+
+    let (json, has_header) = match vault::parse(bytes).map_err(LoadError::Format)? {
+        vault::VaultFile::Plain(json) => {
+            if !creds.is_empty() {
+                return Err(DecryptError::NotEncrypted.into());
+            }
+            (secret::SecretBytes::new(json.to_vec()), true)
+        },
+        vault::VaultFile::Slotted { slots, sealed } => {
+            let key = unlock_slots(slots, creds)?;
+            let json = key.open(sealed).ok_or(DecryptError::WrongPassword)?;
+            master_pk = Some(key);
+            (secret::SecretBytes::new(json), true)
+        },
+        vault::VaultFile::Legacy(bytes) => {
+            // Keyfiles didn't exist back then, but get added when the file is upgraded
+            if let Some(ref pass) = creds.password {
+                // Sealed before passwords went through a KDF, upgraded on next save
+                if let Ok(json) = orion::aead::open(&crypt::legacy_key(pass), bytes) {
+                    (secret::SecretBytes::new(json), false)
+
+                } else if serde_json::from_slice::<serde::de::IgnoredAny>(bytes).is_ok() {
+                    return Err(DecryptError::NotEncrypted.into());
+
+                } else {
+                    return Err(DecryptError::WrongPassword.into());
+                }
+
+            } else if creds.keyfile.is_some() {
+                return Err(DecryptError::NotEncrypted.into());
+
+            } else {
+                (secret::SecretBytes::new(bytes.to_vec()), false)
+            }
+        },
+    };
+
+    match serde_json::from_slice::<Passwords>(&json) {
+        Ok(passwords) => Ok((passwords, master_pk)),
+        Err(err) => Err(ParseError::new(&err, !has_header).into()),
+    }
+}
+
+fn unlock_slots(slots: Vec<crypt::KeySlot>, creds: &crypt::Credentials) -> Result<crypt::MasterKey, Error> {
+    // Point out what's missing if no slot even takes this kind of credentials
+    if !slots.iter().any(|slot| slot.accepts(creds)) {
+        let slot = &slots[0];
+        return Err(match (slot.password, slot.keyfile) {
+            (true, _) if creds.password.is_none() => DecryptError::PasswordRequired,
+            (_, true) if creds.keyfile.is_none() => DecryptError::KeyfileRequired,
+            (_, false) if creds.keyfile.is_some() => DecryptError::KeyfileNotUsed,
+            _ => DecryptError::PasswordNotUsed,
+        }.into());
+    }
+
+    match crypt::MasterKey::unlock(slots, creds) {
+        Ok(Some(key)) => Ok(key),
+        Ok(None) => Err(DecryptError::WrongPassword.into()),
+        Err(err) => Err(DecryptError::KdfParams(err).into()),
+    }
+}
+
+/// Changes key slots in the header, copying the sealed payload over as it is
+fn edit_slots(filename: &str, action: SlotAction, creds: &crypt::Credentials, new_creds: crypt::Credentials, params: crypt::KdfParams, keep_backups: usize) -> Result<(), Error> {
+    let bytes = fs::read(filename).map_err(LoadError::Read)?;
+    let (slots, sealed) = match vault::parse(&bytes).map_err(LoadError::Format)? {
+        vault::VaultFile::Slotted { slots, sealed } => (slots, sealed),
+        _ => return Err(LoadError::NoKeySlots.into()),
+    };
+    let mut key = unlock_slots(slots, creds)?;
+    let slot_index = |index: usize| index.checked_sub(1).filter(|&i| i < key.slots.len()).ok_or(LoadError::NoSlot(index));
+
+    match action {
+        SlotAction::List => {},
+        SlotAction::Add(label) => {
+            if new_creds.is_empty() {
+                return Err(EncryptError::NoNewCredentials.into());
+            }
+            if key.slots.len() >= vault::MAX_SLOTS {
+                return Err(EncryptError::TooManySlots.into());
+            }
+            key.add_slot(label, &new_creds, params).map_err(EncryptError::KdfParams)?;
+            eprintln!("Added key slot {}", key.slots.len());
+        },
+        SlotAction::Label(index, label) => {
+            let index = slot_index(index)?;
+            key.slots[index].label = label;
+        },
+        SlotAction::Revoke(index) => {
+            let index = slot_index(index)?;
+            if key.slots.len() == 1 {
+                return Err(SaveError::LastSlot.into());
+            }
+            key.revoke_slot(index);
+            eprintln!("Revoked key slot {}", index + 1);
+        },
+    }
+
+    save_data(filename, &vault::write_sealed(&key.slots, sealed), Some(&key), keep_backups).map_err(SaveError::Write)?;
+    Ok(())
+}
+
+/// Saves the data file, taking any slots revoked since it was opened out of the backups too -
+/// they'd otherwise still open them, and with them the data key every later save is sealed with
+fn save_data(filename: &str, bytes: &[u8], master_key: Option<&crypt::MasterKey>, keep_backups: usize) -> io::Result<()> {
+    let key = match master_key {
+        Some(key) if !key.revoked.is_empty() => key,
+        _ => return store::save(Path::new(filename), bytes, keep_backups),
+    };
+
+    store::save_scrubbed(Path::new(filename), bytes, keep_backups, |backup| {
+        match vault::parse(backup) {
+            Ok(vault::VaultFile::Slotted { slots, sealed }) if slots.iter().any(|slot| key.revoked.contains(&slot.salt)) => {
+                // Sealed with the same data key, so the slots that open the data file can open this too
+                if key.open(sealed).map(secret::SecretBytes::new).is_some() {
+                    return Some(vault::write_sealed(&key.slots, sealed));
+                }
+                let slots: Vec<_> = slots.into_iter().filter(|slot| !key.revoked.contains(&slot.salt)).collect();
+                if slots.is_empty() {
+                    None
+                } else {
+                    Some(vault::write_sealed(&slots, sealed))
+                }
+            },
+            _ => Some(backup.to_vec()),
+        }
+    })
+}
+
+/// Opens the data file with a key that's already been unlocked, for the agent
+fn reopen_data(filename: &Path, master_key: Option<&crypt::MasterKey>) -> Result<Passwords, Error> {
+    let bytes = fs::read(filename).map_err(LoadError::Read)?;
+    let json = match (vault::parse(&bytes).map_err(LoadError::Format)?, master_key) {
+        (vault::VaultFile::Slotted { sealed, .. }, Some(key)) => secret::SecretBytes::new(key.open(sealed).ok_or(DecryptError::WrongPassword)?),
+        (vault::VaultFile::Plain(json), None) => secret::SecretBytes::new(json.to_vec()),
+        (vault::VaultFile::Plain(_), Some(_)) => return Err(DecryptError::NotEncrypted.into()),
+        _ => return Err(DecryptError::WrongPassword.into()),
+    };
+    serde_json::from_slice(&json).map_err(|err| ParseError::new(&err, false).into())
+}
+
+/// Output of print mode, whether it's printed here or sent back by the agent
+fn script_output(password_set: &mut Passwords, request: &agent::Request) -> Result<secret::SecretString, Error> {
+    // Sized up front, growing would leave copies of the passwords behind
+    let capacity = password_set.pass.iter().map(|pass| {
+        pass.name.len() + pass.password.len() + pass.username.len() + pass.urls.iter().map(|url| url.len() + 5).sum::<usize>()
+            + pass.notes.len() * 2 + pass.rules.len() + pass.history.iter().map(|old| old.password.len() + 25).sum::<usize>() + pass.fields.iter().map(|field| field.name.len() + field.value.len() + 2).sum::<usize>() + 32
+    }).sum::<usize>()
+        + password_set.totp.iter().map(|totp| totp.name.len() * 6 + totp.data.secret.len() * 2 + totp.data.digits + 120).sum::<usize>();
+    let mut output = secret::SecretString::with_capacity(capacity);
+    let mut line = |fields: &[&str]| {
+        for (index, field) in fields.iter().enumerate() {
+            if index != 0 {
+                output.push('\t');
+            }
+            output.push_str(field);
+        }
+        output.push('\n');
+    };
+
+    match request {
+        agent::Request::List => {
+            password_set.pass.iter().for_each(|pass| line(&["pass", &pass.name]));
+            password_set.totp.iter().for_each(|totp| line(&["totp", &totp.name]));
+        },
+        agent::Request::Pass(None) => {
+            password_set.pass.iter().for_each(|pass| line(&[&pass.name, &pass.password]));
+        },
+        agent::Request::Pass(Some(name)) => {
+            let pass = password_set.pass.iter().find(|pass| pass.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            line(&[&pass.password]);
+        },
+        agent::Request::Show(name) => {
+            let pass = password_set.pass.iter().find(|pass| pass.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            if !pass.username.is_empty() {
+                line(&["username", &pass.username]);
+            }
+            pass.urls.iter().for_each(|url| line(&["url", url]));
+            if !pass.notes.is_empty() {
+                line(&["notes", &pass::escape_lines(&pass.notes)]);
+            }
+            if !pass.rules.is_empty() {
+                line(&["rules", &pass.rules]);
+            }
+            pass.fields.iter().filter(|field| !field.secret).for_each(|field| line(&[&field.name, &field.value]));
+        },
+        agent::Request::Field(name, key) => {
+            let pass = password_set.pass.iter().find(|pass| pass.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            let value = pass.get_field(key).ok_or_else(|| LoadError::NoField(name.clone(), key.clone()))?;
+            line(&[&value]);
+        },
+        agent::Request::Audit(json, breaches) => {
+            let mut report = audit::audit(&password_set.pass, &password_set.totp);
+            if let Some(breaches) = breaches {
+                let mut dump = breach::Dump::open(Path::new(breaches)).map_err(LoadError::Breaches)?;
+                report.breached = Some(audit::breached(&password_set.pass, &mut dump).map_err(LoadError::Breaches)?);
+            }
+            if *json {
+                output.push_str(&serde_json::to_string_pretty(&report).unwrap());
+                output.push('\n');
+            } else {
+                output.push_str(&report.human());
+            }
+        },
+        agent::Request::Due(days) => {
+            let deadline = stamp::now() + days * stamp::DAY;
+            for pass in &password_set.pass {
+                if let Some(due) = pass.due().filter(|&due| due <= deadline) {
+                    line(&[&pass.name, &stamp::format(due)]);
+                }
+            }
+        },
+        agent::Request::History(name) => {
+            let pass = password_set.pass.iter().find(|pass| pass.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            for (index, old) in (1..).zip(&pass.history) {
+                line(&[&index.to_string(), &old.replaced_time(), &old.password]);
+            }
+        },
+        agent::Request::Totp(None) => {
+            for totp in &mut password_set.totp {
+                totp.calculate_codes();
+                line(&[&totp.name, totp.get_code(false)]);
+            }
+        },
+        agent::Request::Totp(Some(name)) => {
+            let totp = password_set.totp.iter_mut().find(|totp| totp.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            totp.calculate_codes();
+            line(&[totp.get_code(false)]);
+        },
+        agent::Request::Uri(name) => {
+            let totp = password_set.totp.iter().find(|totp| totp.name == *name).ok_or_else(|| LoadError::NoItem(name.clone()))?;
+            line(&[&otpauth::to_uri(totp)]);
+        },
+        agent::Request::Codes => {
+            // As stored rather than as URIs, so nothing is lost on the way to a backup
+            return Ok(secret::SecretString::new(serde_json::to_string(&password_set.totp).unwrap()));
+        },
+        agent::Request::Stop => {},
+    }
+
+    Ok(output)
+}
+
+/// Codes from an `otpauth://` URI or a Google Authenticator export, exiting if it's neither
+fn codes_from_uri(uri: &str) -> Vec<totp::TotpCode> {
+    if !migration::is_migration(uri) {
+        return vec![otpauth::parse(uri).unwrap_or_else(|err| Error::from(LoadError::Uri(err)).exit())];
+    }
+
+    let batch = migration::parse(uri).unwrap_or_else(|err| Error::from(LoadError::Uri(err)).exit());
+    for name in &batch.skipped {
+        eprintln!("Skipped `{}`, it's a counter based HOTP code and passrs only does TOTP", name);
+    }
+    if batch.size > 1 {
+        eprintln!("This was QR code {} of {} from the export, import the others too", batch.index + 1, batch.size);
+    }
+    batch.codes
+}
 
-    let mut padded_bytes = [0u8; 32];
-    let key_bytes = key.as_bytes();
+fn print_output(output: &secret::SecretString, uri_output: Option<&UriOutput>) {
+    let Some(uri_output) = uri_output else {
+        print!("{}", output.as_str());
+        return;
+    };
 
-    // "Unstable", apparently - ok boomer
-    /*
-    for (pad, byte) in &mut padded.zip(key.as_bytes()) {
-        *pad = *byte;
+    match uri_output {
+        UriOutput::QrTerminal => {
+            let matrix = qr::encode(output.trim_end()).unwrap_or_else(|err| Error::from(LoadError::Qr(err)).exit());
+            let mut stdout = stdout();
+            for line in matrix.half_blocks() {
+                queue!(stdout,
+                       style::SetForegroundColor(style::Color::Black),
+                       style::SetBackgroundColor(style::Color::White),
+                       style::Print(line.as_str()),
+                       style::ResetColor,
+                       style::Print("\n"));
+            }
+            stdout.flush();
+        },
+        UriOutput::QrFile(path) => {
+            let matrix = qr::encode(output.trim_end()).unwrap_or_else(|err| Error::from(LoadError::Qr(err)).exit());
+            matrix.save(Path::new(path)).unwrap_or_else(|err| Error::from(SaveError::Qr(err)).exit());
+        },
+        UriOutput::Backup(format, path) => {
+            let codes: Vec<totp::TotpCode> = serde_json::from_str(output).unwrap_or_else(|err| Error::from(LoadError::Agent(err.to_string())).exit());
+            export_backup(&codes, *format, path);
+        },
     }
-    */
+}
 
-    // C gang lesgo
-    for i in 0..(min(key.len(), padded_bytes.len())) {
-        padded_bytes[i] = key_bytes[i];
+/// Print mode with the data file open here, where `totp export-all` can have the codes as they are
+fn print_local(password_set: &mut Passwords, request: &agent::Request, uri_output: Option<&UriOutput>) {
+    match uri_output {
+        Some(UriOutput::Backup(format, path)) => export_backup(&password_set.totp, *format, path),
+        _ => print_output(&script_output(password_set, request).unwrap_or_else(|err| err.exit()), uri_output),
     }
+}
+
+fn export_backup(codes: &[totp::TotpCode], format: authbackup::Format, path: &str) {
+    let password = env::var("PASSRS_BACKUP_PASS").ok().map(secret::SecretString::new);
+    let skipped = authbackup::export(codes, format, password.as_deref().map(String::as_str), Path::new(path))
+        .unwrap_or_else(|err| match err {
+            authbackup::BackupError::Write(_) => Error::from(SaveError::Backup(err)),
+            err => Error::from(LoadError::Backup(err)),
+        }.exit());
+    for name in &skipped {
+        eprintln!("Skipped `{}`, it has no secret", name);
+    }
+    eprintln!("Exported {} TOTP codes", codes.len() - skipped.len());
+}
+
+/// Refreshes the keyring's copy of the data key, if caching is turned on
+fn cache_key(filename: &str, master_key: Option<&crypt::MasterKey>, ttl: u64) {
+    if ttl == 0 {
+        return;
+    }
+    let Ok(file) = fs::canonicalize(filename) else { return };
+
+    let cached = match master_key {
+        Some(key) => keyring::store(&file, key.data_key(), ttl as usize),
+        None => keyring::forget(&file).map(|_| ()),
+    };
+    if let Err(err) = cached {
+        eprintln!("{}", Error::from(LoadError::Keyring(err)));
+    }
+}
+
+fn lock_data_file(filename: &str) -> store::VaultLock {
+    match store::VaultLock::acquire(Path::new(filename)) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => Error::from(LoadError::Locked).exit(),
+        Err(err) => Error::from(LoadError::Lock(err)).exit(),
+    }
+}
+
+fn new_master_key(creds: &crypt::Credentials, params: crypt::KdfParams) -> crypt::MasterKey {
+    crypt::MasterKey::new(creds, params).unwrap_or_else(|err| Error::from(EncryptError::KdfParams(err)).exit())
+}
 
-    return SecretKey::from_slice(&padded_bytes);
+fn change_slot(key: &mut crypt::MasterKey, creds: &crypt::Credentials, params: crypt::KdfParams) {
+    key.replace_slot(creds, params).unwrap_or_else(|err| Error::from(EncryptError::KdfParams(err)).exit())
 }
@@ -0,0 +1,70 @@
+use serde::Serialize;
+use super::{pass, totp};
+
+pub trait Named {
+    fn name(&self) -> &str;
+}
+
+impl Named for pass::Password {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Named for totp::TotpCode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Three-way merge of items changed on disk into ours, matching items by name.
+/// Returns the names of items changed on both sides - both versions are kept.
+pub fn merge<T: Named + Serialize + Clone>(ours: &mut Vec<T>, base: &[T], theirs: &[T]) -> Vec<String> {
+    // Compare what would be saved, ignoring cached codes and deletion marks
+    let same = |a: &T, b: &T| saved_value(a) == saved_value(b);
+    let find = |items: &[T], name: &str| items.iter().position(|item| item.name() == name);
+    let mut conflicts = Vec::new();
+
+    for their_item in theirs {
+        match find(base, their_item.name()) {
+            // Added on disk
+            None if !ours.iter().any(|item| same(item, their_item)) => {
+                ours.push(their_item.clone());
+            },
+            // Changed on disk
+            Some(base_index) if !same(&base[base_index], their_item) => {
+                match find(ours, their_item.name()) {
+                    Some(our_index) if same(&ours[our_index], &base[base_index]) => {
+                        ours[our_index] = their_item.clone();
+                    },
+                    Some(our_index) if same(&ours[our_index], their_item) => {},
+                    _ => {
+                        conflicts.push(their_item.name().to_string());
+                        ours.push(their_item.clone());
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Deleted on disk, only dropped if we didn't touch it either
+    for base_item in base {
+        if find(theirs, base_item.name()).is_none() {
+            if let Some(our_index) = ours.iter().position(|item| same(item, base_item)) {
+                ours.remove(our_index);
+            }
+        }
+    }
+
+    conflicts
+}
+
+/// What an item would be saved as, minus when it was last copied - just copying one isn't a change to merge
+fn saved_value<T: Serialize>(item: &T) -> Option<serde_json::Value> {
+    let mut value = serde_json::to_value(item).ok()?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove("used");
+    }
+    Some(value)
+}
@@ -0,0 +1,219 @@
+use base64ct::{ Base64, Base64Unpadded, Encoding };
+use totp_rs::Algorithm;
+use zeroize::Zeroize;
+use super::{ otpauth::{ self, UriError }, totp::TotpCode };
+
+// Google Authenticator's "Transfer accounts" QR codes, `otpauth-migration://offline?data=...` with the
+// data a base64 protobuf message. Decoded by hand as there are only two messages to it:
+//     MigrationPayload { repeated OtpParameters otp_parameters = 1; int32 version = 2;
+//                        int32 batch_size = 3; int32 batch_index = 4; int32 batch_id = 5; }
+//     OtpParameters { bytes secret = 1; string name = 2; string issuer = 3; Algorithm algorithm = 4;
+//                     DigitCount digits = 5; OtpType type = 6; int64 counter = 7; }
+// Big exports are split over several QR codes, each a batch that stands on its own
+
+const SCHEME: &str = "otpauth-migration://";
+
+/// One QR code's worth of an export
+pub struct Batch {
+    pub codes: Vec<TotpCode>,
+    /// Names of HOTP codes, which passrs doesn't do
+    pub skipped: Vec<String>,
+    /// Which QR code of how many this was, counting from 0
+    pub index: u64,
+    pub size: u64,
+}
+
+/// Protobuf fields, with only the wire types the payload uses kept
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.0.split_first()?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    /// The next field's number and value, `None` at the end, `Some(Err)` if it's cut short or garbled
+    fn field(&mut self) -> Option<Result<(u64, Value<'a>), UriError>> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = self.varint()?;
+            let value = match key & 7 {
+                0 => Value::Varint(self.varint()?),
+                1 => { self.take(8)?; Value::Other },
+                2 => { let len = self.varint()?; Value::Bytes(self.take(usize::try_from(len).ok()?)?) },
+                5 => { self.take(4)?; Value::Other },
+                _ => return None,
+            };
+            Some((key >> 3, value))
+        })();
+        Some(field.ok_or_else(|| UriError::BadParameter("data".to_string())))
+    }
+}
+
+pub fn is_migration(uri: &str) -> bool {
+    uri.trim_start().get(..SCHEME.len()).is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+}
+
+pub fn parse(uri: &str) -> Result<Batch, UriError> {
+    let query = Some(uri.trim())
+        .filter(|uri| is_migration(uri))
+        .and_then(|uri| uri.split_once('?'))
+        .map(|(_, query)| query)
+        .ok_or(UriError::NotOtpauth)?;
+    let data = query.split('&')
+        .find_map(|pair| pair.strip_prefix("data="))
+        .ok_or(UriError::NoSecret)?;
+
+    let bad = || UriError::BadParameter("data".to_string());
+    // `+` is part of base64 here, not a space
+    let mut data = otpauth::decode(data, false).ok_or_else(bad)?;
+    let payload = Base64::decode_vec(&data).or_else(|_| Base64Unpadded::decode_vec(&data));
+    data.zeroize();
+    let mut payload = payload.map_err(|_| bad())?;
+
+    let batch = read_payload(&payload);
+    payload.zeroize();
+    batch
+}
+
+fn read_payload(payload: &[u8]) -> Result<Batch, UriError> {
+    let mut batch = Batch { codes: Vec::new(), skipped: Vec::new(), index: 0, size: 1 };
+    let mut reader = Reader(payload);
+    while let Some(field) = reader.field() {
+        match field? {
+            (1, Value::Bytes(params)) => read_params(params, &mut batch)?,
+            (3, Value::Varint(size)) => batch.size = size.max(1),
+            (4, Value::Varint(index)) => batch.index = index,
+            _ => {},
+        }
+    }
+    Ok(batch)
+}
+
+fn read_params(params: &[u8], batch: &mut Batch) -> Result<(), UriError> {
+    let mut code = TotpCode::new();
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let mut hotp = false;
+
+    let mut reader = Reader(params);
+    while let Some(field) = reader.field() {
+        let bad = |param: &str| UriError::BadParameter(param.to_string());
+        match field? {
+            (1, Value::Bytes(secret)) => code.set_secret_bytes(secret.to_vec()),
+            (2, Value::Bytes(text)) => name = String::from_utf8(text.to_vec()).map_err(|_| bad("name"))?,
+            (3, Value::Bytes(text)) => issuer = String::from_utf8(text.to_vec()).map_err(|_| bad("issuer"))?,
+            (4, Value::Varint(algorithm)) => code.data.algorithm = match algorithm {
+                0 | 1 => Algorithm::SHA1,
+                2 => Algorithm::SHA256,
+                3 => Algorithm::SHA512,
+                _ => return Err(bad("algorithm")),
+            },
+            (5, Value::Varint(digits)) => code.data.digits = match digits {
+                0 | 1 => 6,
+                2 => 8,
+                _ => return Err(bad("digits")),
+            },
+            (6, Value::Varint(kind)) => hotp = kind == 1,
+            _ => {},
+        }
+    }
+
+    if let Some(name) = otpauth::join_name(&issuer, &name) {
+        code.name = name;
+    }
+    if hotp {
+        batch.skipped.push(code.name.clone());
+    } else if code.data.secret.is_empty() {
+        return Err(UriError::NoSecret);
+    } else {
+        batch.codes.push(code);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Google's own example of an export with one code
+    const GOOGLE: &str = "otpauth-migration://offline?data=CjEKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAhABGAEgACjr4JKK%2Bv%2F%2F%2F%2F8B";
+    /// First of two QR codes, with a SHA-256 8 digit code, a plain one and a HOTP one
+    const BATCH: &str = "otpauth-migration://offline?data=CjsKFD3GyqSCSm0oh2eyMx4gtDFmy4XZEhJqb2huLmRvZUBlbWFpbC5jb20aB0FDTUUgQ28gAigCMAI4AAouChQxMjM0NTY3ODkwMTIzNDU2Nzg5MBIIV29yazpib2IaBFdvcmsgASgBMAI4AAojCgphYmNkZWZnaGlqEgdjb3VudGVyGgRCYW5rIAEoATABOAAQARgCIAAouWA%3D";
+
+    #[test]
+    fn google_example() {
+        assert!(is_migration(GOOGLE));
+        let batch = parse(GOOGLE).unwrap();
+        assert_eq!((batch.index, batch.size), (0, 1));
+        assert!(batch.skipped.is_empty());
+
+        let [code] = &batch.codes[..] else { panic!("{} codes", batch.codes.len()) };
+        assert_eq!(code.name, "Example:alice@google.com");
+        assert_eq!(code.data.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(code.data.algorithm, Algorithm::SHA1);
+        assert_eq!(code.data.digits, 6);
+    }
+
+    #[test]
+    fn batch() {
+        let batch = parse(BATCH).unwrap();
+        assert_eq!((batch.index, batch.size), (0, 2));
+        assert_eq!(batch.skipped, ["Bank:counter"]);
+
+        let [acme, work] = &batch.codes[..] else { panic!("{} codes", batch.codes.len()) };
+        assert_eq!(acme.name, "ACME Co:john.doe@email.com");
+        assert_eq!(acme.data.algorithm, Algorithm::SHA256);
+        assert_eq!(acme.data.digits, 8);
+        assert_eq!(work.name, "Work:bob");
+        assert_eq!(work.data.secret, b"12345678901234567890");
+        assert_eq!(work.data.algorithm, Algorithm::SHA1);
+        assert_eq!(work.data.digits, 6);
+    }
+
+    #[test]
+    fn payloads() {
+        // A code with no secret
+        assert!(matches!(read_payload(b"\x0a\x03\x12\x01a"), Err(UriError::NoSecret)));
+        // Cut off partway through a code
+        assert!(matches!(read_payload(b"\x0a\x10\x0a\x02ab"), Err(UriError::BadParameter(_))));
+        assert!(matches!(read_payload(b"\x0a\x05\x0a\x01a\x20\x07"), Err(UriError::BadParameter(param)) if param == "algorithm"));
+        // Fields it doesn't know about are passed over, and a batch size of 0 is taken as 1
+        let batch = read_payload(b"\x0a\x05\x0a\x01a\x48\x01\x18\x00\x28\x09").unwrap();
+        assert_eq!((batch.codes.len(), batch.size), (1, 1));
+        assert!(read_payload(b"").unwrap().codes.is_empty());
+    }
+
+    #[test]
+    fn not_migrations() {
+        assert!(!is_migration("otpauth://totp/a?secret=AAAA"));
+        assert!(matches!(parse("otpauth://totp/a?secret=AAAA"), Err(UriError::NotOtpauth)));
+        assert!(matches!(parse("otpauth-migration://offline?foo=bar"), Err(UriError::NoSecret)));
+        assert!(matches!(parse("otpauth-migration://offline?data=!!!"), Err(UriError::BadParameter(_))));
+    }
+}
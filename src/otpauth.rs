@@ -0,0 +1,290 @@
+use std::fmt;
+use totp_rs::{ Algorithm, Secret };
+use zeroize::Zeroize;
+use super::{ totp::TotpCode, secret::SecretString };
+
+// Key URIs as other authenticators import and export them, per Google's format:
+//     otpauth://totp/Issuer:account?secret=BASE32&issuer=Issuer&algorithm=SHA1&digits=6&period=30
+// Only the secret is required, the rest default to what TotpCode::new uses
+
+const SCHEME: &str = "otpauth://";
+
+#[derive(Debug)]
+pub enum UriError {
+    NotOtpauth,
+    NotTotp(String),
+    NoSecret,
+    BadSecret,
+    BadParameter(String),
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UriError::NotOtpauth => write!(f, "not an `otpauth://` URI"),
+            UriError::NotTotp(kind) => write!(f, "`{}` codes aren't supported, only `totp`", kind),
+            UriError::NoSecret => write!(f, "it has no secret"),
+            UriError::BadSecret => write!(f, "its secret isn't base32"),
+            UriError::BadParameter(param) => write!(f, "cannot understand its `{}`", param),
+        }
+    }
+}
+
+/// `%XX` escapes, and `+` for a space in query values since some exporters use it
+pub fn decode(text: &str, plus_is_space: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &after[2..];
+                continue;
+            },
+            b'+' if plus_is_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+        rest = after;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Escapes everything but unreserved characters and those in `keep`
+fn encode(text: &str, keep: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || keep.as_bytes().contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+pub fn algorithm_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::SHA1 => "SHA1",
+        Algorithm::SHA256 => "SHA256",
+        Algorithm::SHA512 => "SHA512",
+    }
+}
+
+pub fn parse_algorithm(name: &str) -> Option<Algorithm> {
+    match name.to_ascii_uppercase().as_str() {
+        "SHA1" => Some(Algorithm::SHA1),
+        "SHA256" => Some(Algorithm::SHA256),
+        "SHA512" => Some(Algorithm::SHA512),
+        _ => None,
+    }
+}
+
+/// Secrets get typed with spaces, lowercase and padding often enough
+pub fn decode_secret(base32: &str) -> Result<Vec<u8>, UriError> {
+    // Secret wipes itself when dropped
+    let secret = Secret::Encoded(base32.chars().filter(|c| !c.is_whitespace() && *c != '=').flat_map(char::to_uppercase).collect());
+    secret.to_bytes().map_err(|_| UriError::BadSecret)
+}
+
+/// Code name from an issuer and account, `Issuer:account` like otpauth:// labels, unless the account already has it
+pub fn join_name(issuer: &str, account: &str) -> Option<String> {
+    match (issuer.trim(), account.trim()) {
+        ("", "") => None,
+        ("", account) => Some(account.to_string()),
+        (issuer, "") => Some(issuer.to_string()),
+        (issuer, account) if account.starts_with(&format!("{}:", issuer)) => Some(account.to_string()),
+        (issuer, account) => Some(format!("{}:{}", issuer, account)),
+    }
+}
+
+/// Anything before a `:` in a code's name is the issuer
+pub fn split_name(name: &str) -> (&str, &str) {
+    match name.split_once(':') {
+        Some((issuer, account)) if !issuer.trim().is_empty() => (issuer.trim(), account.trim()),
+        _ => ("", name.trim()),
+    }
+}
+
+pub fn parse(uri: &str) -> Result<TotpCode, UriError> {
+    let uri = uri.trim();
+    let rest = uri.get(..SCHEME.len())
+        .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        .map(|_| &uri[SCHEME.len()..])
+        .ok_or(UriError::NotOtpauth)?;
+    let (kind, rest) = rest.split_once('/').ok_or(UriError::NotOtpauth)?;
+    if !kind.eq_ignore_ascii_case("totp") {
+        return Err(UriError::NotTotp(kind.to_string()));
+    }
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let label = decode(label, false).ok_or_else(|| UriError::BadParameter("label".to_string()))?;
+
+    let mut code = TotpCode::new();
+    let mut issuer: Option<String> = None;
+    let mut secret: Option<String> = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let bad = || UriError::BadParameter(key.to_string());
+        let value = decode(value, true).ok_or_else(bad)?;
+        match key.to_ascii_lowercase().as_str() {
+            "secret" => secret = Some(value),
+            "issuer" => issuer = Some(value).filter(|issuer| !issuer.is_empty()),
+            "algorithm" => code.data.algorithm = parse_algorithm(&value).ok_or_else(bad)?,
+            "digits" => code.data.digits = value.parse().ok().filter(|digits| (4..=8).contains(digits)).ok_or_else(bad)?,
+            "period" => code.data.step = value.parse().ok().filter(|&period| period != 0).ok_or_else(bad)?,
+            // Unknown ones, like `image` from some providers, don't change the codes
+            _ => {},
+        }
+    }
+
+    let mut secret = secret.filter(|secret| !secret.is_empty()).ok_or(UriError::NoSecret)?;
+    let bytes = decode_secret(&secret);
+    secret.zeroize();
+    code.set_secret_bytes(bytes?);
+
+    // The label usually has the issuer already, but it's only required in the parameter
+    let issuer = issuer.filter(|_| !label.contains(':')).unwrap_or_default();
+    if let Some(name) = join_name(&issuer, &label) {
+        code.name = name;
+    }
+    Ok(code)
+}
+
+/// The code's name is the label, with anything before a `:` as the issuer
+pub fn to_uri(code: &TotpCode) -> SecretString {
+    let mut secret = code.data.get_secret_base32();
+
+    let mut uri = SecretString::with_capacity(SCHEME.len() + code.name.len() * 6 + secret.len() + 64);
+    uri.push_str(SCHEME);
+    uri.push_str("totp/");
+    uri.push_str(&encode(&code.name, ":"));
+    uri.push_str("?secret=");
+    uri.push_str(&secret);
+    secret.zeroize();
+    let (issuer, _) = split_name(&code.name);
+    if !issuer.is_empty() {
+        uri.push_str("&issuer=");
+        uri.push_str(&encode(issuer, ""));
+    }
+    uri.push_str(&format!("&algorithm={}&digits={}&period={}", algorithm_name(code.data.algorithm), code.data.digits, code.data.step));
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "JBSWY3DPEHPK3PXP";
+
+    fn uri(query: &str) -> String {
+        format!("otpauth://totp/ACME:jo?secret={}&{}", SECRET, query)
+    }
+
+    #[test]
+    fn round_trip() {
+        for name in ["ACME Co:jo@example.com", "just an account", "Ünïcode & co:?#=%+ /"] {
+            let mut code = TotpCode::new();
+            code.name = name.to_string();
+            code.set_secret_bytes(b"12345678901234567890".to_vec());
+            code.data.algorithm = Algorithm::SHA256;
+            code.data.digits = 8;
+            code.data.step = 60;
+
+            let parsed = parse(&to_uri(&code)).unwrap();
+            assert_eq!(parsed.name, name);
+            assert_eq!(parsed.data.secret, b"12345678901234567890");
+            assert_eq!(parsed.data.algorithm, Algorithm::SHA256);
+            assert_eq!(parsed.data.digits, 8);
+            assert_eq!(parsed.data.step, 60);
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let code = parse(&format!("otpauth://totp/jo?secret={}", SECRET)).unwrap();
+        assert_eq!(code.name, "jo");
+        assert_eq!(code.data.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(code.data.algorithm, Algorithm::SHA1);
+        assert_eq!((code.data.digits, code.data.step), (6, 30));
+        // Schemes and kinds are case insensitive, and there's no need for a label
+        assert!(parse(&format!("OTPAUTH://TOTP/?secret={}", SECRET)).is_ok());
+    }
+
+    #[test]
+    fn issuers() {
+        let name = |uri: &str| parse(uri).unwrap().name;
+        // Only in the label, only in the query, in both
+        assert_eq!(name(&format!("otpauth://totp/ACME:jo?secret={}", SECRET)), "ACME:jo");
+        assert_eq!(name(&format!("otpauth://totp/jo?secret={}&issuer=ACME", SECRET)), "ACME:jo");
+        assert_eq!(name(&format!("otpauth://totp/ACME:jo?secret={}&issuer=ACME", SECRET)), "ACME:jo");
+        // The label wins when they don't agree
+        assert_eq!(name(&format!("otpauth://totp/ACME:jo?issuer=Other&secret={}", SECRET)), "ACME:jo");
+        // Issuer with no account, and an empty issuer parameter
+        assert_eq!(name(&format!("otpauth://totp/?secret={}&issuer=ACME", SECRET)), "ACME");
+        assert_eq!(name(&format!("otpauth://totp/jo?secret={}&issuer=", SECRET)), "jo");
+        assert_eq!(name(&format!("otpauth://totp/ACME%20Co%3A%20jo%40example.com?secret={}&issuer=ACME+Co", SECRET)), "ACME Co: jo@example.com");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(decode("a%20b+c%2Bd", true).unwrap(), "a b c+d");
+        assert_eq!(decode("a%20b+c%2Bd", false).unwrap(), "a b+c+d");
+        assert_eq!(decode("%C3%BCber", false).unwrap(), "über");
+        assert_eq!(decode("%c3%bcber", false).unwrap(), "über");
+        assert!(decode("%2", false).is_none());
+        assert!(decode("%zz", false).is_none());
+        assert!(decode("%FF", false).is_none());
+        assert_eq!(encode("a b:c/ü", ":"), "a%20b:c%2F%C3%BC");
+    }
+
+    #[test]
+    fn secrets() {
+        assert_eq!(decode_secret("jbsw y3dp ehpk 3pxp").unwrap(), b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(decode_secret("MZXW6===").unwrap(), b"foo");
+        assert_eq!(decode_secret("mzxw6").unwrap(), b"foo");
+        assert!(matches!(decode_secret("MZXW1"), Err(UriError::BadSecret)));
+        assert_eq!(parse(&format!("otpauth://totp/jo?secret={}", "jbswy3dpehpk3pxp")).unwrap().data.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(parse("otpauth://totp/jo?secret=MZXW6%3D%3D%3D").unwrap().data.secret, b"foo");
+    }
+
+    #[test]
+    fn bad_parameters() {
+        let bad = |query: &str, param: &str| {
+            assert!(matches!(parse(&uri(query)), Err(UriError::BadParameter(p)) if p == param), "{}", query);
+        };
+        bad("digits=9", "digits");
+        bad("digits=3", "digits");
+        bad("digits=six", "digits");
+        bad("period=0", "period");
+        bad("period=-30", "period");
+        bad("algorithm=MD5", "algorithm");
+        bad("issuer=%zz", "issuer");
+
+        // Lowercase algorithms and unknown parameters are fine
+        assert_eq!(parse(&uri("algorithm=sha512&image=x")).unwrap().data.algorithm, Algorithm::SHA512);
+    }
+
+    #[test]
+    fn not_totp() {
+        assert!(matches!(parse("https://example.com"), Err(UriError::NotOtpauth)));
+        assert!(matches!(parse("otpauth://totp"), Err(UriError::NotOtpauth)));
+        assert!(matches!(parse(&format!("otpauth://hotp/jo?secret={}&counter=1", SECRET)), Err(UriError::NotTotp(kind)) if kind == "hotp"));
+        assert!(matches!(parse("otpauth://totp/jo"), Err(UriError::NoSecret)));
+        assert!(matches!(parse("otpauth://totp/jo?secret="), Err(UriError::NoSecret)));
+        assert!(matches!(parse("otpauth://totp/jo?secret=!!!!"), Err(UriError::BadSecret)));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(join_name("ACME", "jo"), Some("ACME:jo".to_string()));
+        assert_eq!(join_name(" ACME ", "ACME:jo"), Some("ACME:jo".to_string()));
+        assert_eq!(join_name("", " jo "), Some("jo".to_string()));
+        assert_eq!(join_name("ACME", ""), Some("ACME".to_string()));
+        assert_eq!(join_name(" ", ""), None);
+
+        assert_eq!(split_name("ACME Co: jo@example.com"), ("ACME Co", "jo@example.com"));
+        assert_eq!(split_name("A:b:c"), ("A", "b:c"));
+        assert_eq!(split_name("jo"), ("", "jo"));
+        assert_eq!(split_name(" :jo"), ("", ":jo"));
+    }
+}
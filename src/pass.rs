@@ -1,20 +1,206 @@
-use rand::{Rng, thread_rng, distributions::Standard};
 use serde::{Serialize, Deserialize};
+use std::{ cell::OnceCell, mem };
+use super::{gen, stamp, strength, secret::SecretString};
+
+pub const DEFAULT_HISTORY: usize = 10;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Password {
     pub name: String,
-    pub password: String,
+    pub password: SecretString,
+    // Everything below is optional, so data files from before these existed still load
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: SecretString,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<Field>,
+    /// The site's password constraints, in `passwordrules` syntax
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rules: String,
+    /// Previous passwords, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<OldPassword>,
+    /// When it was made, last edited and last copied
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub created: i64,
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub modified: i64,
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub used: i64,
+    /// When the password itself last changed, which rotation counts from
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub changed: i64,
+    /// When the password stops working, 0 for never
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub expires: i64,
+    /// How many days after changing the password it should be changed again, 0 for never
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub rotate_days: i64,
     #[serde(skip)]
     pub delete: bool,
+    /// Strength score, worked out on first use since the list shows it every frame
+    #[serde(skip)]
+    score: OnceCell<u8>,
+}
+
+/// User-defined extra value, like a PIN or security question
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Field {
+    pub name: String,
+    pub value: SecretString,
+    /// Hidden like the password unless asked for by name
+    #[serde(default)]
+    pub secret: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OldPassword {
+    pub password: SecretString,
+    /// When it stopped being the password, in seconds since the Unix epoch
+    pub replaced: i64,
+}
+
+impl OldPassword {
+    pub fn replaced_time(&self) -> String {
+        stamp::format(self.replaced)
+    }
 }
 
 impl Password {
-    pub fn new() -> Password {
-        Password {
+    /// A new password made by `profile`, which fails if it can't meet its own limits
+    pub fn new(profile: &gen::Profile) -> Result<Password, gen::ProfileError> {
+        let now = stamp::now();
+        Ok(Password {
             name: "New Password".to_string(),
-            password: String::from_iter(thread_rng().sample_iter::<char, &Standard>(&Standard).take(32)),
+            password: profile.generate()?,
+            username: String::new(),
+            urls: Vec::new(),
+            notes: SecretString::default(),
+            fields: Vec::new(),
+            rules: String::new(),
+            history: Vec::new(),
+            created: now, modified: now, used: 0, changed: now,
+            expires: 0, rotate_days: 0,
             delete: false,
+            score: OnceCell::new(),
+        })
+    }
+
+    /// Marks this as an edited copy of `old`, keeping the old password if it changed
+    pub fn edited(&mut self, old: &Password, limit: usize) {
+        self.modified = stamp::now();
+        if self.password != old.password {
+            self.remember(old.password.clone(), limit);
+        }
+    }
+
+    /// Keeps a password that was just replaced, forgetting the oldest past `limit`
+    fn remember(&mut self, old_password: SecretString, limit: usize) {
+        let now = stamp::now();
+        self.history.insert(0, OldPassword { password: old_password, replaced: now });
+        self.history.truncate(limit);
+        self.score = OnceCell::new();
+        self.changed = now;
+        self.modified = now;
+    }
+
+    /// Switches back to an old password, the current one taking its place in the history.
+    /// `false` if there's no such old password.
+    pub fn restore(&mut self, index: usize, limit: usize) -> bool {
+        if index >= self.history.len() {
+            return false;
+        }
+        let old = self.history.remove(index);
+        let current = mem::replace(&mut self.password, old.password.clone());
+        self.remember(current, limit);
+        true
+    }
+
+    /// 0 (very weak) to 4 (strong), see `strength::estimate`
+    pub fn score(&self) -> u8 {
+        *self.score.get_or_init(|| strength::estimate(&self.password).score)
+    }
+
+    /// When the password should be changed by, the sooner of its expiry date and rotation.
+    /// Rotation counts from the last password change, or for entries older than timestamps, the last edit.
+    pub fn due(&self) -> Option<i64> {
+        let since = [self.changed, self.created, self.modified].into_iter().find(|&time| time != 0).unwrap_or(0);
+        let rotation = Some(self.rotate_days).filter(|&days| days != 0).map(|days| since + days * stamp::DAY);
+        let expiry = Some(self.expires).filter(|&expires| expires != 0);
+        rotation.into_iter().chain(expiry).min()
+    }
+
+    pub fn expired(&self, now: i64) -> bool {
+        self.due().is_some_and(|due| due <= now)
+    }
+
+    /// Expiry date and rotation for editing, like `2027-01-31 90d`
+    pub fn expiry_text(&self) -> String {
+        let mut parts = Vec::new();
+        if self.expires != 0 {
+            parts.push(stamp::format_date(self.expires));
+        }
+        if self.rotate_days != 0 {
+            parts.push(format!("{}d", self.rotate_days));
+        }
+        parts.join(" ")
+    }
+
+    /// Opposite of `expiry_text`, `false` (changing nothing) if it doesn't make sense
+    pub fn set_expiry_text(&mut self, text: &str) -> bool {
+        let (mut expires, mut rotate_days) = (0, 0);
+        for part in text.split_whitespace() {
+            match part.strip_suffix('d').map(str::parse) {
+                Some(Ok(days)) if days > 0 => rotate_days = days,
+                _ => match stamp::parse_date(part) {
+                    Some(date) => expires = date,
+                    None => return false,
+                },
+            }
+        }
+        self.expires = expires;
+        self.rotate_days = rotate_days;
+        true
+    }
+
+    /// Looks up `password`, `username`, `url`, `notes`, `rules` or a custom field by name, URLs one per line
+    pub fn get_field(&self, key: &str) -> Option<SecretString> {
+        match key {
+            "password" => Some(self.password.clone()),
+            "username" => Some(SecretString::new(self.username.clone())),
+            "url" => Some(SecretString::new(self.urls.join("\n"))),
+            "notes" => Some(self.notes.clone()),
+            "rules" => Some(SecretString::new(self.rules.clone())),
+            _ => self.fields.iter().find(|field| field.name == key).map(|field| field.value.clone()),
+        }
+    }
+}
+
+/// Notes on one line for editing and printing, newlines become `\n` and backslashes `\\`
+pub fn escape_lines(string: &str) -> SecretString {
+    let mut escaped = SecretString::with_capacity(string.len() * 2);
+    for c in string.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape_lines(string: &str) -> SecretString {
+    let mut unescaped = SecretString::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => { unescaped.push('\n'); chars.next(); },
+            ('\\', Some('\\')) => { unescaped.push('\\'); chars.next(); },
+            (c, _) => unescaped.push(c),
         }
     }
+    unescaped
 }
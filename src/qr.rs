@@ -0,0 +1,231 @@
+use std::{ fmt, fs, io::{ self, Write }, os::unix::fs::OpenOptionsExt, path::Path };
+use image::{ ImageReader, GrayImage, ImageEncoder, codecs::png::PngEncoder };
+use qrcode::{ QrCode, Color };
+use zeroize::Zeroize;
+use super::secret::SecretString;
+
+// QR codes in pictures, like the ones sites show when setting up 2FA - screenshots work
+// as well as photos, and dark mode's light-on-dark codes get a second try inverted.
+// Going the other way, codes are drawn in the terminal or saved as PNG or SVG for phones to scan,
+// and since they hold secrets the files are only readable by us
+
+/// Light modules around the code, scanners want 4 but terminals are short on room
+pub const TERMINAL_QUIET: usize = 2;
+const FILE_QUIET: usize = 4;
+/// Pixels per module in PNGs
+const PNG_SCALE: usize = 8;
+
+#[derive(Debug)]
+pub enum QrError {
+    Read(io::Error),
+    Image(image::ImageError),
+    NotFound,
+    Decode(rqrr::DeQRError),
+    Encode(qrcode::types::QrError),
+    Write(io::Error),
+}
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QrError::Read(err) => write!(f, "Cannot read QR code image: {}", err),
+            QrError::Image(err) => write!(f, "Cannot read QR code image: {}", err),
+            QrError::NotFound => write!(f, "Cannot find a QR code in the image"),
+            QrError::Decode(err) => write!(f, "Found a QR code but couldn't read it: {}", err),
+            QrError::Encode(err) => write!(f, "Cannot make a QR code: {}", err),
+            QrError::Write(err) => write!(f, "Cannot write QR code image: {}", err),
+        }
+    }
+}
+
+/// Text of every QR code in a PNG or JPEG, whatever its file extension says
+pub fn decode(path: &Path) -> Result<Vec<String>, QrError> {
+    let image = ImageReader::open(path).map_err(QrError::Read)?
+        .with_guessed_format().map_err(QrError::Read)?
+        .decode().map_err(QrError::Image)?
+        .to_luma8();
+
+    let mut last_err = None;
+    for inverted in [false, true] {
+        let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(image.width() as usize, image.height() as usize, |x, y| {
+            let luma = image.get_pixel(x as u32, y as u32).0[0];
+            if inverted { 255 - luma } else { luma }
+        });
+
+        let mut texts = Vec::new();
+        for grid in prepared.detect_grids() {
+            match grid.decode() {
+                Ok((_, text)) => texts.push(text),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        if !texts.is_empty() {
+            return Ok(texts);
+        }
+    }
+    Err(last_err.map_or(QrError::NotFound, QrError::Decode))
+}
+
+/// Which modules are dark, wiped when dropped
+pub struct Matrix {
+    width: usize,
+    dark: Vec<u8>,
+}
+
+impl Drop for Matrix {
+    fn drop(&mut self) {
+        self.dark.zeroize();
+    }
+}
+
+pub fn encode(text: &str) -> Result<Matrix, QrError> {
+    let code = QrCode::new(text.as_bytes()).map_err(QrError::Encode)?;
+    let dark = code.to_colors().into_iter().map(|color| (color == Color::Dark) as u8).collect();
+    Ok(Matrix { width: code.width(), dark })
+}
+
+impl Matrix {
+    /// Width and height with the quiet zone, in modules
+    pub fn size(&self, quiet: usize) -> usize {
+        self.width + quiet * 2
+    }
+
+    /// Counting from the outside of the quiet zone, which is never dark
+    fn is_dark(&self, x: usize, y: usize, quiet: usize) -> bool {
+        let (Some(x), Some(y)) = (x.checked_sub(quiet), y.checked_sub(quiet)) else { return false };
+        x < self.width && y < self.width && self.dark[y * self.width + x] != 0
+    }
+
+    /// Two rows of modules per line of half blocks, to be drawn black on white
+    pub fn half_blocks(&self) -> Vec<SecretString> {
+        let size = self.size(TERMINAL_QUIET);
+        (0..size).step_by(2).map(|y| {
+            let mut line = SecretString::with_capacity(size * 3);
+            for x in 0..size {
+                line.push(match (self.is_dark(x, y, TERMINAL_QUIET), self.is_dark(x, y + 1, TERMINAL_QUIET)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            line
+        }).collect()
+    }
+
+    fn svg(&self) -> SecretString {
+        let size = self.size(FILE_QUIET);
+        let mut svg = SecretString::with_capacity(256 + self.dark.len() * 16);
+        svg.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" shape-rendering=\"crispEdges\">\n", size));
+        svg.push_str(&format!("<rect width=\"{0}\" height=\"{0}\" fill=\"#fff\"/>\n<path fill=\"#000\" d=\"", size));
+        for y in 0..size {
+            for x in (0..size).filter(|&x| self.is_dark(x, y, FILE_QUIET)) {
+                svg.push_str(&format!("M{},{}h1v1h-1z", x, y));
+            }
+        }
+        svg.push_str("\"/>\n</svg>\n");
+        svg
+    }
+
+    fn png(&self, file: &mut fs::File) -> Result<(), image::ImageError> {
+        let size = self.size(FILE_QUIET);
+        let mut image = GrayImage::from_fn((size * PNG_SCALE) as u32, (size * PNG_SCALE) as u32, |x, y| {
+            let dark = self.is_dark(x as usize / PNG_SCALE, y as usize / PNG_SCALE, FILE_QUIET);
+            image::Luma([if dark { 0 } else { 255 }])
+        });
+        let written = PngEncoder::new(file).write_image(&image, image.width(), image.height(), image::ExtendedColorType::L8);
+        image.zeroize();
+        written
+    }
+
+    /// SVG if the file name ends in `.svg`, otherwise PNG
+    pub fn save(&self, path: &Path) -> Result<(), QrError> {
+        let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path).map_err(QrError::Write)?;
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg")) {
+            file.write_all(self.svg().as_bytes()).map_err(QrError::Write)
+        } else {
+            self.png(&mut file).map_err(|err| match err {
+                image::ImageError::IoError(err) => QrError::Write(err),
+                err => QrError::Write(io::Error::other(err)),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ os::unix::fs::PermissionsExt, process };
+
+    const URI: &str = "otpauth://totp/Example:me@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example";
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("passrs-qr-test-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let path = temp_path("code.png");
+        encode(URI).unwrap().save(&path).unwrap();
+        let texts = decode(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(texts.unwrap(), [URI]);
+    }
+
+    #[test]
+    fn inverted() {
+        let matrix = encode(URI).unwrap();
+        let size = matrix.size(FILE_QUIET);
+        let image = GrayImage::from_fn((size * PNG_SCALE) as u32, (size * PNG_SCALE) as u32, |x, y| {
+            let dark = matrix.is_dark(x as usize / PNG_SCALE, y as usize / PNG_SCALE, FILE_QUIET);
+            image::Luma([if dark { 255 } else { 0 }])
+        });
+        let path = temp_path("inverted.png");
+        image.save(&path).unwrap();
+        let texts = decode(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(texts.unwrap(), [URI]);
+    }
+
+    #[test]
+    fn no_code() {
+        let path = temp_path("blank.png");
+        GrayImage::from_pixel(64, 64, image::Luma([255])).save(&path).unwrap();
+        let result = decode(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(QrError::NotFound)));
+
+        assert!(matches!(decode(&temp_path("missing.png")), Err(QrError::Read(_))));
+    }
+
+    #[test]
+    fn half_blocks() {
+        let matrix = encode("hi").unwrap();
+        let lines = matrix.half_blocks();
+        let size = matrix.size(TERMINAL_QUIET);
+        assert_eq!(lines.len(), size.div_ceil(2));
+        assert!(lines.iter().all(|line| line.chars().count() == size));
+        // The quiet zone is blank, and the finder pattern's corner is dark top and bottom
+        assert!(lines[0].chars().all(|c| c == ' '));
+        assert_eq!(lines[TERMINAL_QUIET / 2].chars().nth(TERMINAL_QUIET), Some('█'));
+    }
+
+    #[test]
+    fn svg() {
+        let matrix = encode("hi").unwrap();
+        let size = matrix.size(FILE_QUIET);
+        let svg = matrix.svg();
+        assert!(svg.starts_with(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\"", size)));
+        let dark = matrix.dark.iter().filter(|&&dark| dark != 0).count();
+        assert_eq!(svg.matches("h1v1h-1z").count(), dark);
+        assert!(svg.contains(&format!("M{0},{0}h1v1h-1z", FILE_QUIET)));
+
+        let path = temp_path("code.svg");
+        matrix.save(&path).unwrap();
+        let saved = fs::read_to_string(&path);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.unwrap(), svg.as_str());
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{ fmt, ops::{ Deref, DerefMut } };
+use serde::{ Serialize, Deserialize };
+use zeroize::Zeroize;
+
+/// String that's wiped from memory when dropped.
+/// Edits that grow past its capacity still leave the old allocation behind, so reserve generously.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(string: String) -> SecretString {
+        SecretString(string)
+    }
+
+    /// Empty string with room for typing a password without reallocating
+    pub fn with_capacity(capacity: usize) -> SecretString {
+        SecretString(String::with_capacity(capacity))
+    }
+}
+
+impl Deref for SecretString {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretString {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.0
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretString(..)")
+    }
+}
+
+/// Byte buffer that's kept out of swap where the OS lets us, and wiped when dropped
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> SecretBytes {
+        // Best effort, RLIMIT_MEMLOCK is often tiny
+        #[cfg(unix)]
+        unsafe {
+            libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.capacity());
+        }
+        SecretBytes(bytes)
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        #[cfg(unix)]
+        unsafe {
+            libc::munlock(self.0.as_ptr() as *const libc::c_void, self.0.capacity());
+        }
+    }
+}
+
+/// Stops the kernel writing core dumps (or letting other processes ptrace us) with secrets in them
+pub fn harden_process() {
+    #[cfg(unix)]
+    unsafe {
+        let no_core = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
@@ -0,0 +1,36 @@
+use chrono::{ DateTime, Local, NaiveDate, Utc };
+
+// Times are kept as seconds since the Unix epoch, with 0 for "don't know" -
+// anything saved before they were recorded, or never copied
+
+pub const DAY: i64 = 24 * 60 * 60;
+
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// For leaving unknown times and unset intervals out of the data file
+pub fn is_unset(value: &i64) -> bool {
+    *value == 0
+}
+
+/// Local date and time, or `unknown`
+pub fn format(time: i64) -> String {
+    match DateTime::from_timestamp(time, 0).filter(|_| time != 0) {
+        Some(time) => time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+pub fn format_date(time: i64) -> String {
+    match DateTime::from_timestamp(time, 0) {
+        Some(time) => time.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+        None => String::new(),
+    }
+}
+
+/// Start of a `YYYY-MM-DD` day, local time
+pub fn parse_date(date: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+    date.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).earliest().map(|time| time.timestamp())
+}
@@ -0,0 +1,148 @@
+use std::{ fs::{ self, OpenOptions }, io::{ self, Write }, path::{ Path, PathBuf }, process, time };
+use orion::hash::{ digest, Digest };
+
+pub const DEFAULT_BACKUPS: usize = 5;
+
+/// Advisory lock on `FILE.lock`, held for as long as this is alive
+pub struct VaultLock {
+    _file: fs::File,
+}
+
+impl VaultLock {
+    /// Locks the data file, or `None` if another passrs already has it
+    pub fn acquire(path: &Path) -> io::Result<Option<VaultLock>> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(suffixed(path, ".lock"))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Some(VaultLock { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(fs::TryLockError::Error(err)) => Err(err),
+        }
+    }
+}
+
+/// Hash of the data file as it was read, to notice if something else wrote to it since
+#[derive(PartialEq)]
+pub struct Fingerprint(Option<Digest>);
+
+impl Fingerprint {
+    pub fn of(bytes: Option<&[u8]>) -> Fingerprint {
+        Fingerprint(bytes.and_then(|b| digest(b).ok()))
+    }
+}
+
+pub struct Backup {
+    pub path: PathBuf,
+    pub time: time::SystemTime,
+}
+
+/// Replaces the data file without ever leaving it half-written, keeping the old one as a backup
+pub fn save(path: &Path, bytes: &[u8], keep_backups: usize) -> io::Result<()> {
+    let backup = if keep_backups != 0 && path.exists() { Some(fs::read(path)?) } else { None };
+    replace(path, bytes, keep_backups, backup)
+}
+
+/// Saves like `save`, but first runs every backup and the file being backed up through `scrub`,
+/// which gives what they should hold instead, or `None` to get rid of them
+pub fn save_scrubbed(path: &Path, bytes: &[u8], keep_backups: usize, scrub: impl Fn(&[u8]) -> Option<Vec<u8>>) -> io::Result<()> {
+    for backup in list_backups(path)? {
+        let old_bytes = fs::read(&backup.path)?;
+        match scrub(&old_bytes) {
+            Some(new_bytes) if new_bytes == old_bytes => {},
+            Some(new_bytes) => write_atomic(&backup.path, &new_bytes)?,
+            None => fs::remove_file(&backup.path)?,
+        }
+    }
+
+    let backup = if keep_backups != 0 && path.exists() { scrub(&fs::read(path)?) } else { None };
+    replace(path, bytes, keep_backups, backup)
+}
+
+fn replace(path: &Path, bytes: &[u8], keep_backups: usize, backup: Option<Vec<u8>>) -> io::Result<()> {
+    if let Some(backup) = backup {
+        let millis = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_millis();
+        write_atomic(&suffixed(path, &format!(".bak.{}", millis)), &backup)?;
+
+        for old_backup in list_backups(path)?.iter().skip(keep_backups) {
+            fs::remove_file(&old_backup.path)?;
+        }
+    }
+
+    write_atomic(path, bytes)
+}
+
+/// Backups of the data file, newest first
+pub fn list_backups(path: &Path) -> io::Result<Vec<Backup>> {
+    let prefix = format!("{}.bak.", file_name(path));
+    let mut backups = Vec::new();
+
+    for entry in fs::read_dir(parent_dir(path))? {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        if let Some(millis) = name.to_str().and_then(|n| n.strip_prefix(&prefix)).and_then(|m| m.parse::<u64>().ok()) {
+            backups.push(Backup {
+                path: entry.path(),
+                time: time::UNIX_EPOCH + time::Duration::from_millis(millis),
+            });
+        }
+    }
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.time));
+    Ok(backups)
+}
+
+/// Puts a backup back in place of the data file, which is itself backed up first
+pub fn restore_backup(path: &Path, backup: &Backup, keep_backups: usize) -> io::Result<()> {
+    let bytes = fs::read(&backup.path)?;
+    // Always back up the current file, even with backups turned off, so restoring can be undone
+    save(path, &bytes, keep_backups.max(1))
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp_path = parent_dir(path).join(format!(".{}.tmp{}", file_name(path), process::id()));
+
+    let written = (|| {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return written;
+    }
+
+    // The rename itself only survives a crash once the directory is synced
+    #[cfg(unix)]
+    fs::File::open(parent_dir(path))?.sync_all()?;
+
+    Ok(())
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
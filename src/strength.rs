@@ -0,0 +1,216 @@
+use std::{ collections::HashMap, sync::OnceLock };
+use super::gen;
+
+// Guesses an attacker would need, the way zxcvbn does it: find the patterns a cracker tries first
+// (common passwords, words, keyboard runs, sequences, repeats, years), then the cheapest way to
+// cover the whole password with them and plain brute force
+
+/// Most used passwords from breach dumps, roughly most common first
+const COMMON: [&str; 100] = [
+    "123456", "password", "123456789", "12345678", "12345", "qwerty", "1234567", "111111", "1234567890", "123123",
+    "abc123", "1234", "password1", "iloveyou", "1q2w3e4r", "000000", "qwerty123", "zaq12wsx", "dragon", "sunshine",
+    "princess", "letmein", "654321", "monkey", "27653", "1qaz2wsx", "123321", "qwertyuiop", "superman", "asdfghjkl",
+    "football", "baseball", "welcome", "admin", "login", "master", "hello", "freedom", "whatever", "qazwsx",
+    "trustno1", "starwars", "passw0rd", "shadow", "michael", "jennifer", "hunter", "hunter2", "charlie", "ashley",
+    "bailey", "mustang", "access", "flower", "killer", "batman", "soccer", "jordan", "harley", "ranger",
+    "buster", "thomas", "tigger", "robert", "daniel", "andrew", "pepper", "summer", "ginger", "hockey",
+    "joshua", "cheese", "maggie", "secret", "computer", "internet", "matrix", "samsung", "google", "pokemon",
+    "minecraft", "naruto", "liverpool", "chelsea", "arsenal", "banana", "cookie", "chocolate", "butterfly", "purple",
+    "orange", "lovely", "angel", "friends", "family", "changeme", "default", "test", "guest", "root",
+];
+
+const KEYBOARD_ROWS: [&str; 5] = ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./", "qwertzuiop"];
+
+/// Longer than any word or keyboard row, and long sequences are cheap anyway
+const MAX_PATTERN: usize = 32;
+/// Guesses for a word from the EFF list, which has no ranking - about the middle of a big dictionary
+const WORD_GUESSES: f64 = 5000.0;
+
+pub struct Strength {
+    /// Rough log10 of the guesses needed to crack it
+    pub guesses_log10: f64,
+    /// 0 (very weak) to 4 (strong)
+    pub score: u8,
+}
+
+impl Strength {
+    pub fn label(&self) -> &'static str {
+        ["very weak", "weak", "fair", "good", "strong"][self.score as usize]
+    }
+}
+
+/// Common passwords and words, with how many guesses each takes
+fn dictionary() -> &'static HashMap<&'static str, f64> {
+    static DICTIONARY: OnceLock<HashMap<&'static str, f64>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let mut dictionary: HashMap<&'static str, f64> = gen::wordlist().iter().map(|&word| (word, WORD_GUESSES)).collect();
+        for (rank, password) in (1..).zip(COMMON) {
+            dictionary.insert(password, rank as f64);
+        }
+        dictionary
+    })
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Guesses to brute force one character, by what kind of character it is
+fn cardinality(c: char) -> f64 {
+    match c {
+        '0'..='9' => 10.0,
+        'a'..='z' | 'A'..='Z' => 26.0,
+        c if c.is_ascii() => 33.0,
+        _ => 1000.0,
+    }
+}
+
+/// Cheapest pattern covering `chars[start..end]`, in guesses, if there is one
+fn pattern_guesses(chars: &[char], start: usize, end: usize) -> Option<f64> {
+    let part = &chars[start..end];
+    let len = part.len();
+    let lower: String = part.iter().flat_map(|c| c.to_lowercase()).collect();
+    let mut best: Option<f64> = None;
+    let mut consider = |guesses: f64| best = Some(best.map_or(guesses, |best| best.min(guesses)));
+
+    if len >= 3 {
+        // Capitals anywhere but the start, or l33t, make a word a bit harder
+        let capitals = if part.iter().all(|c| !c.is_uppercase()) {
+            1.0
+        } else if part[1..].iter().all(|c| !c.is_uppercase()) || part.iter().all(|c| !c.is_lowercase()) {
+            2.0
+        } else {
+            16.0
+        };
+        if let Some(guesses) = dictionary().get(lower.as_str()) {
+            consider(guesses * capitals);
+        }
+        let unleeted: String = lower.chars().map(unleet).collect();
+        if unleeted != lower {
+            if let Some(guesses) = dictionary().get(unleeted.as_str()) {
+                consider(guesses * capitals * 4.0);
+            }
+        }
+
+        if part.iter().all(|&c| c == part[0]) {
+            consider(cardinality(part[0]) * len as f64);
+        }
+
+        // abc, 2468, zyx
+        let step = part[1] as i64 - part[0] as i64;
+        if (1..=2).contains(&step.abs()) && part.windows(2).all(|pair| pair[1] as i64 - pair[0] as i64 == step) {
+            let base = if "az019AZ".contains(part[0]) { 4.0 } else if part[0].is_ascii_digit() { 10.0 } else { 26.0 };
+            consider(base * len as f64 * if step < 0 { 2.0 } else { 1.0 });
+        }
+
+        if len == 4 && lower.parse::<u32>().is_ok_and(|year| (1900..=2049).contains(&year)) {
+            consider(150.0);
+        }
+    }
+
+    if len >= 4 {
+        let reversed: String = lower.chars().rev().collect();
+        if KEYBOARD_ROWS.iter().any(|row| row.contains(&lower) || row.contains(&reversed)) {
+            consider(40.0 * len as f64);
+        }
+    }
+
+    best
+}
+
+pub fn estimate(password: &str) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+
+    // Fewest guesses (as log10) to get the first `end` characters
+    let mut best = vec![0.0; chars.len() + 1];
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + cardinality(chars[end - 1]).log10();
+        for start in end.saturating_sub(MAX_PATTERN)..end - 1 {
+            if let Some(guesses) = pattern_guesses(&chars, start, end) {
+                // Each extra pattern also means guessing which pattern comes next
+                let pattern = if start == 0 { 0.0 } else { 1.0 };
+                best[end] = f64::min(best[end], best[start] + guesses.log10() + pattern);
+            }
+        }
+    }
+
+    let guesses_log10 = best[chars.len()];
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    Strength { guesses_log10, score }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Log10 guesses to brute force it with no patterns at all
+    fn brute_force(password: &str) -> f64 {
+        password.chars().map(|c| cardinality(c).log10()).sum()
+    }
+
+    fn caught(password: &str) -> bool {
+        estimate(password).guesses_log10 < brute_force(password) - 2.0
+    }
+
+    #[test]
+    fn common_passwords() {
+        for password in COMMON {
+            assert_eq!(estimate(password).score, 0, "{}", password);
+        }
+        assert_eq!(estimate("P@ssw0rd").score, 0);
+        assert_eq!(estimate("").score, 0);
+    }
+
+    #[test]
+    fn diceware() {
+        let profile = gen::Profile::parse("test", "words=6,separator=space").unwrap();
+        for _ in 0..20 {
+            let passphrase = profile.generate().unwrap();
+            assert_eq!(estimate(&passphrase).score, 4, "{}", passphrase.as_str());
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        // Keyboard runs, either way along the row
+        assert!(caught("zxcvbnm,./"));
+        assert!(caught("poiuytrewq"));
+        // Sequences, up or down, by one or two
+        assert!(caught("abcdefghij"));
+        assert!(caught("zyxwvutsr"));
+        assert!(caught("13579135"));
+        // Repeats
+        assert!(caught("aaaaaaaaaaaa"));
+        assert!(caught("%%%%%%%%"));
+        // Words, capitalised or in l33t
+        assert!(caught("Monkey"));
+        assert!(caught("m0nk3y"));
+    }
+
+    #[test]
+    fn years() {
+        assert!(estimate("1987").guesses_log10 < estimate("1897").guesses_log10);
+        assert!(estimate("xkcd2024").guesses_log10 < estimate("xkcd2924").guesses_log10);
+    }
+
+    #[test]
+    fn random_passwords() {
+        assert_eq!(estimate("k8#Vq2!zP@w9").score, 4);
+        assert!((estimate("k8#Vq2!zP@w9").guesses_log10 - brute_force("k8#Vq2!zP@w9")).abs() < 1e-9);
+        assert!(estimate("x7Kq").score < estimate("x7Kq9vLm").score);
+    }
+}
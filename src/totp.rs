@@ -1,55 +1,92 @@
+use std::time;
 use totp_rs::{TOTP, Secret, Algorithm};
 use serde::{Serialize, Deserialize};
+use zeroize::Zeroize;
+use super::{ stamp, secret::SecretString };
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TotpCode {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none", default = "Option::default")]
-    raw_secret: Option<String>,
+    raw_secret: Option<SecretString>,
     pub data: TOTP,
     #[serde(skip, default = "Option::default")]
     cached_codes: Option<(String, String)>,
+    /// Which of its time steps the cached codes are for
+    #[serde(skip, default = "u64::default")]
+    cached_step: u64,
+    /// When it was made, last edited and last copied
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub created: i64,
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub modified: i64,
+    #[serde(default, skip_serializing_if = "stamp::is_unset")]
+    pub used: i64,
     #[serde(skip, default = "bool::default")]
     pub delete: bool,
 }
 
 impl TotpCode {
     pub fn new() -> TotpCode {
+        let now = stamp::now();
         TotpCode {
             name: "New code".to_string(), raw_secret: None,
             data: TOTP::new_unchecked(Algorithm::SHA1, 6, 0, 30, Vec::new()),
-            cached_codes: None, delete: false,
+            cached_codes: None, cached_step: 0, created: now, modified: now, used: 0, delete: false,
         }
     }
 
-    pub fn get_secret_string(&self) -> String {
+    pub fn get_secret_string(&self) -> SecretString {
         if let Some(raw) = &self.raw_secret {
-            raw.to_string()
+            raw.clone()
         } else {
-            self.data.get_secret_base32()
+            SecretString::new(self.data.get_secret_base32())
         }
     }
 
-    pub fn set_secret_string(&mut self, secret: String) {
+    pub fn set_secret_string(&mut self, secret: &str) {
         /*
         self.data.secret = Secret::Encoded(secret.clone()).to_bytes()
             .or(Secret::Raw(secret.as_bytes().to_vec()).to_bytes())
             .unwrap();
         */
-        if let Ok(encoded) = Secret::Encoded(secret.clone()).to_bytes() {
+        self.data.secret.zeroize();
+
+        if let Ok(encoded) = Secret::Encoded(secret.to_string()).to_bytes() {
             self.raw_secret = None;
             self.data.secret = encoded;
         } else {
-            self.raw_secret = Some(secret.clone());
+            self.raw_secret = Some(SecretString::new(secret.to_string()));
             self.data.secret = Secret::Raw(secret.as_bytes().to_vec()).to_bytes().unwrap();
         }
     }
 
+    /// For secrets already decoded, from URIs and other authenticators' exports
+    pub fn set_secret_bytes(&mut self, secret: Vec<u8>) {
+        self.data.secret.zeroize();
+        self.raw_secret = None;
+        self.data.secret = secret;
+    }
+
+    /// Gives the same codes, whatever it's called
+    pub fn same_code(&self, other: &TotpCode) -> bool {
+        self.data.secret == other.data.secret && self.data.algorithm == other.data.algorithm
+            && self.data.digits == other.data.digits && self.data.step == other.data.step
+    }
+
     pub fn calculate_codes(&mut self) {
-        let totp_now = self.data.generate_current().unwrap();
-        let totp_next = self.data.generate(self.data.next_step_current().unwrap());
+        // One reading of the clock, so the codes and the step they're for can't straddle a change
+        let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs();
+        let totp_now = self.data.generate(now);
+        let totp_next = self.data.generate(now + self.data.step);
 
         self.cached_codes = Some((totp_now, totp_next));
+        self.cached_step = now / self.data.step;
+    }
+
+    /// Whether the cached codes have run out, each code going by its own period
+    pub fn codes_expired(&self, now: u64) -> bool {
+        self.cached_codes.is_none() || self.cached_step != now / self.data.step
     }
 
     pub fn get_code(&self, next: bool) -> &str {
@@ -116,6 +116,16 @@ pub fn print_typing(mut x: (u16, u16), y: u16, string: &String, cursor: Option<u
     }
 }
 
+/// Taller and greener the stronger a password is, for a 0-4 strength score
+pub fn strength_mark(score: u8) -> (char, style::Color) {
+    let colour = match score {
+        0 | 1 => style::Color::Red,
+        2 => style::Color::Yellow,
+        _ => style::Color::Green,
+    };
+    (['▁', '▂', '▄', '▆', '█'][score as usize], colour)
+}
+
 pub fn center_offset(center: u16, width: u16) -> u16 {
     return ((center as f32 - width as f32).max(0.0) / 2.0) as u16;
 }
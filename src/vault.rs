@@ -0,0 +1,309 @@
+use std::fmt;
+use super::crypt::{KdfParams, KeySlot, MasterKey, SALT_LEN};
+
+// Header layout, all integers little-endian:
+//     MAGIC, version: u8, flags: u8 (encrypted),
+//     (encrypted only) slot count: u8, then for each key slot:
+//         label length: u8, label, flags: u8 (keyfile, no password), kdf id: u8,
+//         (Argon2id) mem_kib: u32, iterations: u32, parallelism: u32, salt length: u8, salt,
+//         wrapped key length: u8, data key sealed with the slot's derived key
+// followed by the JSON payload, sealed with the data key if the encrypted flag is set.
+pub const MAGIC: &[u8; 6] = b"PASSRS";
+pub const FORMAT_VERSION: u8 = 1;
+/// Slot count is stored in a byte
+pub const MAX_SLOTS: usize = u8::MAX as usize;
+
+const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+const FLAG_KEYFILE: u8 = 0b0000_0010;
+const FLAG_NO_PASSWORD: u8 = 0b0000_0100;
+const KDF_ARGON2ID: u8 = 1;
+
+pub enum VaultFile<'a> {
+    Plain(&'a [u8]),
+    /// Data key wrapped in one or more key slots
+    Slotted { slots: Vec<KeySlot>, sealed: &'a [u8] },
+    /// No header, either raw JSON or sealed with the zero-padded key - can't tell until we try
+    Legacy(&'a [u8]),
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Truncated,
+    NewerVersion(u8),
+    UnknownKdf(u8),
+    UnknownFlags(u8),
+    BadSaltLength(u8),
+    NoSlots,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Truncated => write!(f, "header is corrupt or cut short"),
+            FormatError::NewerVersion(v) => write!(f, "format version {} is newer than this passrs supports ({})", v, FORMAT_VERSION),
+            FormatError::UnknownKdf(id) => write!(f, "header has unknown key derivation function {}", id),
+            FormatError::UnknownFlags(flags) => write!(f, "header has unknown or inconsistent flags {:#010b}", flags),
+            FormatError::BadSaltLength(len) => write!(f, "header has a {}-byte salt, expected {}", len, SALT_LEN),
+            FormatError::NoSlots => write!(f, "header is encrypted but has no key slots"),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn kdf_params(&mut self) -> Result<KdfParams, FormatError> {
+        Ok(KdfParams { mem_kib: self.u32()?, iterations: self.u32()?, parallelism: self.u32()? })
+    }
+
+    fn salt(&mut self) -> Result<[u8; SALT_LEN], FormatError> {
+        Ok(self.take(SALT_LEN)?.try_into().unwrap())
+    }
+
+    fn slot(&mut self) -> Result<KeySlot, FormatError> {
+        let label_len = self.u8()? as usize;
+        let label = String::from_utf8_lossy(self.take(label_len)?).to_string();
+
+        let flags = self.u8()?;
+        let password = flags & FLAG_NO_PASSWORD == 0;
+        let keyfile = flags & FLAG_KEYFILE != 0;
+        if flags & !(FLAG_KEYFILE | FLAG_NO_PASSWORD) != 0 || !(password || keyfile) {
+            return Err(FormatError::UnknownFlags(flags));
+        }
+
+        match self.u8()? {
+            KDF_ARGON2ID => {},
+            kdf => return Err(FormatError::UnknownKdf(kdf)),
+        }
+
+        let params = self.kdf_params()?;
+        let salt_len = self.u8()?;
+        if salt_len as usize != SALT_LEN {
+            return Err(FormatError::BadSaltLength(salt_len));
+        }
+        let salt = self.salt()?;
+
+        let wrapped_len = self.u8()? as usize;
+        let wrapped_key = self.take(wrapped_len)?.to_vec();
+
+        Ok(KeySlot { label, params, salt, password, keyfile, wrapped_key })
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<VaultFile<'_>, FormatError> {
+    if !bytes.starts_with(MAGIC) {
+        return Ok(VaultFile::Legacy(bytes));
+    }
+
+    let mut reader = Reader { bytes: &bytes[MAGIC.len()..] };
+
+    // Migrations from older layouts (or JSON schemas) belong in here
+    match reader.u8()? {
+        1 => {
+            match reader.u8()? {
+                0 => Ok(VaultFile::Plain(reader.bytes)),
+                FLAG_ENCRYPTED => {
+                    let slot_count = reader.u8()?;
+                    if slot_count == 0 {
+                        return Err(FormatError::NoSlots);
+                    }
+                    let slots = (0..slot_count).map(|_| reader.slot()).collect::<Result<Vec<_>, _>>()?;
+                    Ok(VaultFile::Slotted { slots, sealed: reader.bytes })
+                },
+                flags => Err(FormatError::UnknownFlags(flags)),
+            }
+        },
+        0 => Err(FormatError::Truncated),
+        v => Err(FormatError::NewerVersion(v)),
+    }
+}
+
+/// Wraps JSON in the current header, sealing it if there's a key
+pub fn write(master_key: Option<&MasterKey>, json: &[u8]) -> Option<Vec<u8>> {
+    match master_key {
+        Some(key) => Some(write_sealed(&key.slots, &key.seal(json)?)),
+        None => {
+            let mut bytes = Vec::with_capacity(MAGIC.len() + 2 + json.len());
+            bytes.extend_from_slice(MAGIC);
+            bytes.push(FORMAT_VERSION);
+            bytes.push(0);
+            bytes.extend_from_slice(json);
+            Some(bytes)
+        },
+    }
+}
+
+/// Writes the key slots in front of an already sealed payload, so slots can change without touching it
+pub fn write_sealed(slots: &[KeySlot], sealed: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 3 + slots.len() * 128 + sealed.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.push(FLAG_ENCRYPTED);
+    bytes.push(slots.len() as u8);
+
+    for slot in slots {
+        let label = truncated(&slot.label, u8::MAX as usize);
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+
+        let mut flags = 0;
+        if slot.keyfile {
+            flags |= FLAG_KEYFILE;
+        }
+        if !slot.password {
+            flags |= FLAG_NO_PASSWORD;
+        }
+        bytes.push(flags);
+        bytes.push(KDF_ARGON2ID);
+        bytes.extend_from_slice(&slot.params.mem_kib.to_le_bytes());
+        bytes.extend_from_slice(&slot.params.iterations.to_le_bytes());
+        bytes.extend_from_slice(&slot.params.parallelism.to_le_bytes());
+        bytes.push(SALT_LEN as u8);
+        bytes.extend_from_slice(&slot.salt);
+        bytes.push(slot.wrapped_key.len() as u8);
+        bytes.extend_from_slice(&slot.wrapped_key);
+    }
+
+    bytes.extend_from_slice(sealed);
+    bytes
+}
+
+/// Cuts a string down to at most `len` bytes without splitting a character
+fn truncated(string: &str, len: usize) -> &str {
+    let mut end = string.len().min(len);
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+    &string[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ crypt::Credentials, secret::SecretString };
+
+    const JSON: &[u8] = br#"{"pass":[],"totp":[],"ui_colour":0}"#;
+    // As cheap as Argon2 allows, these are only about the layout
+    const PARAMS: KdfParams = KdfParams { mem_kib: 8, iterations: 1, parallelism: 1 };
+
+    fn header(version: u8) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(version);
+        bytes
+    }
+
+    fn push_params(bytes: &mut Vec<u8>, params: KdfParams) {
+        bytes.extend_from_slice(&params.mem_kib.to_le_bytes());
+        bytes.extend_from_slice(&params.iterations.to_le_bytes());
+        bytes.extend_from_slice(&params.parallelism.to_le_bytes());
+    }
+
+    fn password(pass: &str) -> Credentials {
+        Credentials { password: Some(SecretString::new(pass.to_string())), keyfile: None }
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let bytes = write(None, JSON).unwrap();
+        assert!(matches!(parse(&bytes), Ok(VaultFile::Plain(json)) if json == JSON));
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let mut key = MasterKey::new(&password("first"), PARAMS).unwrap();
+        key.add_slot("second".to_string(), &password("second"), PARAMS).unwrap();
+        let bytes = write(Some(&key), JSON).unwrap();
+
+        let VaultFile::Slotted { slots, sealed } = parse(&bytes).unwrap() else { panic!("expected key slots") };
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[1].label, "second");
+        assert_eq!(slots[1].salt, key.slots[1].salt);
+        assert_eq!(slots[1].params, PARAMS);
+        assert!(slots[1].password && !slots[1].keyfile);
+
+        // Either slot opens the same payload
+        for pass in ["first", "second"] {
+            let unlocked = MasterKey::unlock(slots.clone(), &password(pass)).unwrap().unwrap();
+            assert_eq!(unlocked.open(sealed).unwrap(), JSON);
+        }
+        assert!(MasterKey::unlock(slots, &password("wrong")).unwrap().is_none());
+    }
+
+    #[test]
+    fn rewritten_slots_keep_payload() {
+        let mut key = MasterKey::new(&password("first"), PARAMS).unwrap();
+        let bytes = write(Some(&key), JSON).unwrap();
+        let VaultFile::Slotted { sealed, .. } = parse(&bytes).unwrap() else { panic!("expected key slots") };
+
+        key.add_slot("second".to_string(), &password("second"), PARAMS).unwrap();
+        key.revoke_slot(0);
+        let rewritten = write_sealed(&key.slots, sealed);
+
+        let VaultFile::Slotted { slots, sealed: resealed } = parse(&rewritten).unwrap() else { panic!("expected key slots") };
+        assert_eq!(resealed, sealed);
+        assert!(MasterKey::unlock(slots.clone(), &password("first")).unwrap().is_none());
+        let unlocked = MasterKey::unlock(slots, &password("second")).unwrap().unwrap();
+        assert_eq!(unlocked.open(resealed).unwrap(), JSON);
+    }
+
+    #[test]
+    fn long_labels_are_cut_on_a_character() {
+        let mut key = MasterKey::new(&password("first"), PARAMS).unwrap();
+        key.slots[0].label = "é".repeat(200);
+        let bytes = write(Some(&key), JSON).unwrap();
+
+        let VaultFile::Slotted { slots, .. } = parse(&bytes).unwrap() else { panic!("expected key slots") };
+        assert_eq!(slots[0].label, "é".repeat(127));
+    }
+
+    #[test]
+    fn legacy() {
+        assert!(matches!(parse(JSON), Ok(VaultFile::Legacy(json)) if json == JSON));
+        assert!(matches!(parse(b""), Ok(VaultFile::Legacy(b""))));
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert!(matches!(parse(&header(FORMAT_VERSION + 1)), Err(FormatError::NewerVersion(_))));
+        assert!(matches!(parse(MAGIC), Err(FormatError::Truncated)));
+        assert!(matches!(parse(&header(0)), Err(FormatError::Truncated)));
+        assert!(matches!(parse(&[&header(1)[..], &[FLAG_ENCRYPTED, 0]].concat()), Err(FormatError::NoSlots)));
+        assert!(matches!(parse(&[&header(1)[..], &[FLAG_ENCRYPTED, 1, 0]].concat()), Err(FormatError::Truncated)));
+        assert!(matches!(parse(&[&header(1)[..], &[0b1000_0000]].concat()), Err(FormatError::UnknownFlags(_))));
+
+        let slot = |flags: u8, kdf: u8, salt_len: u8| {
+            let mut bytes = header(1);
+            bytes.extend_from_slice(&[FLAG_ENCRYPTED, 1, 0, flags, kdf]);
+            push_params(&mut bytes, PARAMS);
+            bytes.push(salt_len);
+            bytes.extend_from_slice(&[7; SALT_LEN]);
+            bytes.extend_from_slice(&[4, 1, 2, 3, 4]);
+            bytes
+        };
+        assert!(matches!(parse(&slot(FLAG_KEYFILE, KDF_ARGON2ID, SALT_LEN as u8)), Ok(VaultFile::Slotted { .. })));
+        assert!(matches!(parse(&slot(0, 5, SALT_LEN as u8)), Err(FormatError::UnknownKdf(5))));
+        assert!(matches!(parse(&slot(0, KDF_ARGON2ID, 8)), Err(FormatError::BadSaltLength(8))));
+        assert!(matches!(parse(&slot(FLAG_ENCRYPTED, KDF_ARGON2ID, SALT_LEN as u8)), Err(FormatError::UnknownFlags(_))));
+        // A slot with neither a password nor a keyfile could be opened by anyone
+        assert!(matches!(parse(&slot(FLAG_NO_PASSWORD, KDF_ARGON2ID, SALT_LEN as u8)), Err(FormatError::UnknownFlags(_))));
+    }
+}